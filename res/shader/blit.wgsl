@@ -0,0 +1,35 @@
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput
+) -> VertexOutput {
+    var out: VertexOutput;
+    let position = model.position + vec3<f32>(-1.0,1.0,0.0);
+    out.tex_coords = vec2<f32>(
+        model.position[0] * 0.5,
+        - model.position[1] * 0.5,
+    ) ;
+    out.clip_position = vec4<f32>(position, 1.0);
+    return out;
+}
+
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+
+
+// Copies the finished frame onto the output.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureSample(t_diffuse, s_diffuse, in.tex_coords).rgb, 1.0);
+}
@@ -0,0 +1,66 @@
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput
+) -> VertexOutput {
+    var out: VertexOutput;
+    let position = model.position + vec3<f32>(-1.0,1.0,0.0);
+    out.tex_coords = vec2<f32>(
+        model.position[0] * 0.5,
+        - model.position[1] * 0.5,
+    ) ;
+    out.clip_position = vec4<f32>(position, 1.0);
+    return out;
+}
+
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+
+// `CrtParams` is declared in crt_params.wgsl.
+@group(1) @binding(2)
+var<uniform> crt: CrtParams;
+
+
+// Keeps only the light above the threshold, downsampled into the first bloom level.
+@fragment
+fn fs_threshold(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_source, s_source, in.tex_coords).rgb;
+    let brightness = max(color.r, max(color.g, color.b));
+    let weight = smoothstep(crt.bloom_threshold, crt.bloom_threshold + 0.1, brightness);
+    return vec4<f32>(color * weight, 1.0);
+}
+
+@fragment
+fn fs_blur_horizontal(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(blur(in.tex_coords, vec2<f32>(1.0, 0.0)), 1.0);
+}
+
+@fragment
+fn fs_blur_vertical(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(blur(in.tex_coords, vec2<f32>(0.0, 1.0)), 1.0);
+}
+
+// 9-tap Gaussian along `direction`, taps spaced `bloom_radius` texels apart.
+fn blur(tex_coords: vec2<f32>, direction: vec2<f32>) -> vec3<f32> {
+    let weights = array<f32, 5>(0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+    let step = direction * crt.bloom_radius / vec2<f32>(textureDimensions(t_source));
+
+    var color = textureSample(t_source, s_source, tex_coords).rgb * weights[0];
+    for (var i = 1; i < 5; i++) {
+        let offset = step * f32(i);
+        color += textureSample(t_source, s_source, tex_coords + offset).rgb * weights[i];
+        color += textureSample(t_source, s_source, tex_coords - offset).rgb * weights[i];
+    }
+    return color;
+}
@@ -0,0 +1,62 @@
+// Example post pass. Add it to the chain in game_config.toml with
+//
+// [[post]]
+// shader = "colour_grade.wgsl"
+// uniforms = [1.0, 1.0, 0.0, 1.0]   # saturation, contrast, brightness, gamma
+//
+// Post passes share this interface:
+//   group(0) output of the previous pass (the tile pass for the first one)
+//   group(1) binding 0..2: time, ScreenUniform and CrtParams, binding 3: the pass uniforms
+//
+// crt_params.wgsl is appended to every shader, so declare the `crt` binding
+// without redeclaring `CrtParams`.
+//   group(2) output of this pass in the previous frame
+//   group(3) bloom
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput
+) -> VertexOutput {
+    var out: VertexOutput;
+    let position = model.position + vec3<f32>(-1.0,1.0,0.0);
+    out.tex_coords = vec2<f32>(
+        model.position[0] * 0.5,
+        - model.position[1] * 0.5,
+    ) ;
+    out.clip_position = vec4<f32>(position, 1.0);
+    return out;
+}
+
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+
+@group(1) @binding(3)
+var<uniform> params: array<vec4<f32>, 4>;
+
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let saturation = params[0].x;
+    let contrast = params[0].y;
+    let brightness = params[0].z;
+    let gamma = max(params[0].w, 0.01);
+
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords).rgb;
+    let luma = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    var graded = mix(vec3<f32>(luma), color, saturation);
+    graded = (graded - 0.5) * contrast + 0.5 + brightness;
+    graded = pow(max(graded, vec3<f32>(0.0)), vec3<f32>(1.0 / gamma));
+    return vec4<f32>(graded, 1.0);
+}
@@ -9,6 +9,8 @@ struct InstanceInput {
     @location(6) matrix_2: vec4<f32>,
     @location(7) matrix_3: vec4<f32>,
     @location(8) color: vec3<f32>,
+    @location(9) bg: vec3<f32>,
+    @location(10) attr: u32,
 };
 
 struct VertexInput {
@@ -23,7 +25,11 @@ struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
     @location(1) color: vec3<f32>,
-    @location(2) position: vec2<f32>
+    @location(2) position: vec2<f32>,
+    @location(3) bg: vec3<f32>,
+    @location(4) @interpolate(flat) attr: u32,
+    // Position inside the cell, (0,0) at the top left.
+    @location(5) cell: vec2<f32>
 }
 
 @vertex
@@ -47,6 +53,9 @@ fn vs_main(
     let position = model_matrix * vec4<f32>(model.position, 1.0);
     out.clip_position = camera.view_proj *  position;
     out.color = instance.color;
+    out.bg = instance.bg;
+    out.attr = instance.attr;
+    out.cell = vec2<f32>(1.0 - model.tex_coords[0], 1.0 - model.tex_coords[1]);
     out.position = position.xy;
     return out;
 }
@@ -59,14 +68,57 @@ var t_diffuse: texture_2d<f32>;
 var s_diffuse: sampler;
 
 
+@group(2) @binding(0)
+var<uniform> time: vec4<f32>;
+
+
 const alpha_threshold : f32 = 0.5;
 
+// Text attribute flags, mirrored in config.rs.
+const ATTR_BLINK : u32 = 1u;
+const ATTR_UNDERLINE : u32 = 2u;
+const ATTR_REVERSE : u32 = 4u;
+const ATTR_BOLD : u32 = 8u;
+const ATTR_DIM : u32 = 16u;
+const ATTR_TRANSPARENT : u32 = 32u;
+
+const BLINK_PERIOD_MS : f32 = 1000.0;
+const UNDERLINE_HEIGHT : f32 = 0.125;
+const BOLD_BOOST : f32 = 0.3333333;
+const DIM_FACTOR : f32 = 0.5;
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
 //    return vec4<f32>(in.position, 0.0,1.0);
 
 
+    var fg = in.color;
+    var bg = in.bg;
+    if (in.attr & ATTR_REVERSE) != 0u {
+        fg = in.bg;
+        bg = in.color;
+    }
+    if (in.attr & ATTR_BOLD) != 0u {
+        fg = min(fg + vec3<f32>(BOLD_BOOST), vec3<f32>(1.0));
+    }
+    if (in.attr & ATTR_DIM) != 0u {
+        fg = fg * DIM_FACTOR;
+    }
+
     let alpha = textureSample(t_diffuse, s_diffuse, in.tex_coords).a;
-    let colour = in.color * step(alpha_threshold, alpha);
+    var glyph = step(alpha_threshold, alpha);
+    if (in.attr & ATTR_UNDERLINE) != 0u && in.cell.y >= 1.0 - UNDERLINE_HEIGHT {
+        glyph = 1.0;
+    }
+    // Blinking cells show only their background for the second half of each period.
+    if (in.attr & ATTR_BLINK) != 0u && fract(time[0] / BLINK_PERIOD_MS) >= 0.5 {
+        glyph = 0.0;
+    }
+
+    let colour = mix(bg, fg, glyph);
+    // Transparent cells keep only the glyph, blended over the layers below.
+    if (in.attr & ATTR_TRANSPARENT) != 0u {
+        return vec4<f32>(fg, glyph);
+    }
     return vec4<f32>(colour.rgb ,1.0);
 }
@@ -0,0 +1,25 @@
+// Appended to every shader when it is loaded, so passes binding the CRT
+// parameters share one declaration. Mirrors `CrtParams` in crt.rs.
+struct CrtParams {
+    noise_strength: f32,
+    noise_darkening: f32,
+    scanline_density: f32,
+    scanline_depth: f32,
+    border_glow: f32,
+    curvature: f32,
+    corner_radius: f32,
+    vignette: f32,
+    persistence: vec3<f32>,
+    bloom_threshold: f32,
+    bloom_radius: f32,
+    bloom_intensity: f32,
+    mask: u32,
+    mask_strength: f32,
+    mask_scale: f32,
+    interlace: u32,
+    deinterlace: f32,
+    _padding: f32,
+    tint: vec3<f32>,
+    monochrome: f32,
+};
+
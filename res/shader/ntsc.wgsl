@@ -0,0 +1,118 @@
+// Composite video post pass: encodes the image into an NTSC-style YIQ signal
+// and decodes it again, giving colour bleed, fringing and dot crawl.
+//
+// [[post]]
+// shader = "ntsc.wgsl"
+// uniforms = [0.6, 640.0, 3.0, 15.0]   # artifact strength, dots per line, chroma bandwidth, dot crawl speed
+//
+// Dots per line is the horizontal resolution of the signal; at 0 it follows the output width.
+// Chroma bandwidth is the spread of the chroma low-pass in dots, and dot crawl speed
+// the subcarrier phase drift in cycles per second.
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput
+) -> VertexOutput {
+    var out: VertexOutput;
+    let position = model.position + vec3<f32>(-1.0,1.0,0.0);
+    out.tex_coords = vec2<f32>(
+        model.position[0] * 0.5,
+        - model.position[1] * 0.5,
+    ) ;
+    out.clip_position = vec4<f32>(position, 1.0);
+    return out;
+}
+
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+
+@group(1) @binding(0)
+var<uniform> time: vec4<f32>;
+
+struct ScreenUniform {
+    grid_size: vec2<f32>,
+    _padding: vec2<f32>,
+};
+
+@group(1) @binding(1)
+var<uniform> screen: ScreenUniform;
+
+// `CrtParams` is declared in crt_params.wgsl.
+@group(1) @binding(2)
+var<uniform> crt: CrtParams;
+
+@group(1) @binding(3)
+var<uniform> params: array<vec4<f32>, 4>;
+
+const PI: f32 = 3.14159265;
+// Taps on each side of the centre; covers two subcarrier cycles.
+const TAPS: i32 = 8;
+
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let strength = params[0].x;
+    let dots = select(f32(textureDimensions(t_diffuse).x), params[0].y, params[0].y > 0.0);
+    let bandwidth = max(params[0].z, 0.5);
+    let crawl = time[0] * 0.001 * params[0].w * 2.0 * PI;
+
+    // The subcarrier advances a quarter cycle per dot and flips phase every
+    // scanline, with scanlines matching those of the CRT pass.
+    let dot_x = floor(in.tex_coords.x * dots);
+    let line = floor(in.tex_coords.y * screen.grid_size.y * crt.scanline_density);
+    let line_phase = (line % 2.0) * PI + crawl;
+
+    var luma = 0.0;
+    var luma_weight = 0.0;
+    var chroma = vec2<f32>(0.0);
+    var chroma_weight = 0.0;
+    for (var k = -TAPS; k <= TAPS; k++) {
+        let offset = f32(k);
+        let uv = vec2<f32>((dot_x + offset + 0.5) / dots, in.tex_coords.y);
+        let yiq = rgb_to_yiq(textureSample(t_diffuse, s_diffuse, uv).rgb);
+        let phase = (dot_x + offset) * PI * 0.5 + line_phase;
+        let carrier = vec2<f32>(cos(phase), sin(phase));
+        let signal = yiq.x + dot(yiq.yz, carrier);
+
+        // Luma is a box over one subcarrier cycle, which cancels the chroma out of it.
+        let in_cycle = select(0.0, 1.0, k >= -2 && k <= 1);
+        luma += signal * in_cycle;
+        luma_weight += in_cycle;
+
+        let weight = exp(-(offset * offset) / (bandwidth * bandwidth));
+        chroma += signal * carrier * weight;
+        chroma_weight += weight;
+    }
+
+    let decoded = yiq_to_rgb(vec3<f32>(luma / luma_weight, chroma * 2.0 / chroma_weight));
+    let original = textureSample(t_diffuse, s_diffuse, in.tex_coords).rgb;
+    return vec4<f32>(mix(original, clamp(decoded, vec3<f32>(0.0), vec3<f32>(1.0)), strength), 1.0);
+}
+
+fn rgb_to_yiq(rgb: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(
+        dot(rgb, vec3<f32>(0.299, 0.587, 0.114)),
+        dot(rgb, vec3<f32>(0.596, -0.274, -0.322)),
+        dot(rgb, vec3<f32>(0.211, -0.523, 0.312)),
+    );
+}
+
+fn yiq_to_rgb(yiq: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(
+        dot(yiq, vec3<f32>(1.0, 0.956, 0.621)),
+        dot(yiq, vec3<f32>(1.0, -0.272, -0.647)),
+        dot(yiq, vec3<f32>(1.0, -1.106, 1.703)),
+    );
+}
@@ -31,9 +31,34 @@ var t_diffuse: texture_2d<f32>;
 var s_diffuse: sampler;
 
 
+// x: milliseconds since start, y: frame index.
 @group(1) @binding(0)
 var<uniform> time: vec4<f32>;
 
+struct ScreenUniform {
+    grid_size: vec2<f32>,
+    _padding: vec2<f32>,
+};
+
+@group(1) @binding(1)
+var<uniform> screen: ScreenUniform;
+
+// `CrtParams` is declared in crt_params.wgsl.
+@group(1) @binding(2)
+var<uniform> crt: CrtParams;
+
+// Output of this pass in the previous frame.
+@group(2) @binding(0)
+var t_history: texture_2d<f32>;
+@group(2) @binding(1)
+var s_history: sampler;
+
+// Blurred bright parts of the tile pass, at the end of the bloom downsample chain.
+@group(3) @binding(0)
+var t_bloom: texture_2d<f32>;
+@group(3) @binding(1)
+var s_bloom: sampler;
+
 
 
 
@@ -43,31 +68,128 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
 //       return vec4<f32> (in.tex_coords, 0.0,1.0);
 
 
-    let texture = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let uv = distort(in.tex_coords);
+    let bloom = textureSample(t_bloom, s_bloom, uv).rgb * crt.bloom_intensity;
+    let texture = vec4<f32>(apply_tint(textureSample(t_diffuse, s_diffuse, uv).rgb + bloom), 1.0);
+    // Position on the curved tube, in the same -1..1 space as `in.position`.
+    let position = vec3<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, in.position.z);
 
     let alpha_threshold : f32 = 0.5;
-    let tv_border = add_tv_border(in.position.xy);
-    let factor = add_scan_line( in.position.y );
-    let color = add_noise(in.position , texture.rgb);
+    let tv_border = add_tv_border(position.xy);
+    let factor = add_scan_line( position.y );
+    let color = add_noise(position , texture.rgb);
+
+    let tube = tube_mask(uv) * add_vignette(uv);
+    let mask = phosphor_mask(in.clip_position.xy);
+    let field = interlace_field(position.y);
+    let current = (color * factor * mask + tv_border.rgb) * tube * field;
+    let previous = textureSample(t_history, s_history, in.tex_coords).rgb;
+    let deinterlaced = previous * crt.deinterlace * (1.0 - field);
+    return vec4<f32>(max(add_afterglow(current, previous), deinterlaced), 1.0);
+}
+
+// Monochrome tubes show the luminance in their phosphor colour.
+fn apply_tint(color: vec3<f32>) -> vec3<f32> {
+    let luma = dot(color, vec3<f32>(0.299, 0.587, 0.114));
+    return mix(color, vec3<f32>(luma), crt.monochrome) * crt.tint;
+}
+
+// Barrel distortion, kept in sync with `CrtParams::distort` in crt.rs.
+// Edge midpoints stay in place so only the corners bend off the tube.
+fn distort(tex_coords: vec2<f32>) -> vec2<f32> {
+    let centered = tex_coords * 2.0 - 1.0;
+    let warped = centered * (1.0 + crt.curvature * dot(centered, centered)) / (1.0 + crt.curvature);
+    return warped * 0.5 + 0.5;
+}
+
+// 1 inside the rounded tube face, 0 outside.
+fn tube_mask(uv: vec2<f32>) -> f32 {
+    let centered = abs(uv * 2.0 - 1.0);
+    let radius = max(crt.corner_radius, 0.0001);
+    let corner = max(centered - vec2<f32>(1.0 - radius), vec2<f32>(0.0));
+    let dist = length(corner) - radius;
+    // Away from the corners `dist` is `-radius`, so the fade must not be wider.
+    let edge = min(radius, 0.005);
+    return 1.0 - smoothstep(-edge, 0.0, dist);
+}
+
+fn add_vignette(uv: vec2<f32>) -> f32 {
+    let edge = 16.0 * uv.x * uv.y * (1.0 - uv.x) * (1.0 - uv.y);
+    return pow(clamp(edge, 0.0, 1.0), crt.vignette);
+}
 
-    return vec4<f32>( color * factor, 1.0) + tv_border;
+const MASK_OFF: u32 = 0u;
+const MASK_APERTURE_GRILLE: u32 = 1u;
+const MASK_SHADOW: u32 = 2u;
+const MASK_SLOT: u32 = 3u;
+
+// Per-channel weight of the phosphor under an output pixel, after Timothy Lottes' crt masks.
+fn phosphor_mask(frag_coord: vec2<f32>) -> vec3<f32> {
+    if (crt.mask == MASK_OFF) {
+        return vec3<f32>(1.0);
+    }
+    let dark = 1.0 - crt.mask_strength;
+    let light = 1.0 + crt.mask_strength;
+    var pos = floor(frag_coord / max(crt.mask_scale, 1.0));
+    var line = 1.0;
+
+    if (crt.mask == MASK_SHADOW) {
+        // Two pixel wide dots, each row of triads shifted by half a triad.
+        pos.x = fract((pos.x + pos.y * 3.0) / 6.0);
+    } else {
+        if (crt.mask == MASK_SLOT) {
+            // Slots end on alternating rows in neighbouring triads.
+            let odd = select(0.0, 1.0, fract(pos.x / 6.0) < 0.5);
+            line = select(light, dark, fract((pos.y + odd) / 2.0) < 0.5);
+        }
+        pos.x = fract(pos.x / 3.0);
+    }
+
+    var mask = vec3<f32>(dark);
+    if (pos.x < 0.333) {
+        mask.r = light;
+    } else if (pos.x < 0.666) {
+        mask.g = light;
+    } else {
+        mask.b = light;
+    }
+    return mask * line;
+}
+
+// Phosphors light up instantly but fade out over several frames.
+fn add_afterglow(current: vec3<f32>, previous: vec3<f32>) -> vec3<f32> {
+    return max(current, previous * clamp(crt.persistence, vec3<f32>(0.0), vec3<f32>(0.99)));
 }
 
 fn add_noise(modelPos : vec3<f32>, color : vec3<f32>) -> vec3<f32>{
     let mul_model_pos = modelPos * 100.0;
     let t = time[0] * 4.0;
-    let factor1 = 1.0 - time_noise(mul_model_pos, t) * 0.25;
+    let factor1 = 1.0 - time_noise(mul_model_pos, t) * crt.noise_darkening;
     let baseColor = vec3(
       time_noise(mul_model_pos, t),
       time_noise(mul_model_pos, t * 2.0),
       time_noise(mul_model_pos, t * 3.0));
 
-    return baseColor * 0.025 + color * factor1;
+    return baseColor * crt.noise_strength + color * factor1;
 }
 
-fn add_scan_line( modelPosY: f32)-> f32 {
+// Scanline coordinate: integers are the gaps between scanlines.
+fn scan_line_position(modelPosY: f32) -> f32 {
     // Add 10 so we don't have to deal with negative numbers.
-    let t :f32= 10.0 + modelPosY * 24.0 * 8.0;
+    return 10.0 + modelPosY * screen.grid_size.y * crt.scanline_density;
+}
+
+// 1 on the scanlines of the field shown this frame, 0 on the others.
+fn interlace_field(modelPosY: f32) -> f32 {
+    if (crt.interlace == 0u) {
+        return 1.0;
+    }
+    let line = i32(floor(scan_line_position(modelPosY)));
+    return select(0.0, 1.0, ((line + i32(time[1])) & 1) == 0);
+}
+
+fn add_scan_line( modelPosY: f32)-> f32 {
+    let t :f32= scan_line_position(modelPosY);
 
     let distToFloor = fract(t);
     let distToCeil = 1.0 - distToFloor;
@@ -76,7 +198,7 @@ fn add_scan_line( modelPosY: f32)-> f32 {
     // Integers are the black bands, so we want the scanline intensity to
     // be 1 there, and quickly fall towards 0 as we get away from them:
     let intensity = 1.0 - smoothstep(0.0, 0.3, distToNearestInt);
-    let factor = max(0.0, 1.0 - intensity * 0.7);
+    let factor = max(0.0, 1.0 - intensity * crt.scanline_depth);
     return factor;
 }
 
@@ -85,7 +207,7 @@ fn add_tv_border( modelPos : vec2<f32>) -> vec4<f32> {
     let distToBorderV = abs(abs(modelPos.y) - 1.0);
     let distToBorder = min(distToBorderH, distToBorderV);
     let f = 1.0 -smoothstep(0.0, 0.2, distToBorder);
-    return vec4(f, f, f, 1.0) * 0.02;
+    return vec4(f, f, f, 1.0) * crt.border_glow;
 }
 
 
@@ -200,44 +322,3 @@ fn time_noise(modelPos: vec3<f32> , time : f32) -> f32 {
       return 105.0 * dot( m*m, vec4<f32>(dot(p0,x0), dot(p1,x1),dot(p2,x2), dot(p3,x3)));
 }
 
-
-
-//const BLURSCALEX : f32 = 2.45;
-//const LOWLUMSCAN : f32 =  10.0;
-//const HILUMSCAN : f32 =  10.0;
-//const BRIGHTBOOST : f32 =  0.25;
-//const MASK_DARK : f32 =  0.25;
-//const MASK_FADE : f32 =  0.8;
-
-//@fragment
-//fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-//
-//    let texture_size = vec2<f32>(800.0,600.0);
-//    let output_size = vec2<f32>(800.0,600.0);
-//    let input_size = vec2<f32>(800.0,600.0);
-//
-//    let ratio = texture_size.x / input_size.x;
-//
-//    let inv_dims = 1.0 / texture_size;
-//
-//
-//    let p0 = in.tex_coords * texture_size;
-//    let i = floor(p0) + 0.50;
-//    let f = p0 - i;
-//    var p = (i + 4.0*f*f*f) * inv_dims;
-//    p.x = mix( p.x , in.tex_coords.x, BLURSCALEX);
-//
-//    let Y = f.y*f.y;
-//    let YY = Y*Y;
-//
-//    let whichmask = floor(in.tex_coords.x * output_size.x * ratio)*-0.5;
-//    let mask = 1.0 + f32(fract(whichmask) < 0.5) * -MASK_DARK;
-//
-//
-//    let colour = vec3<f32>(1.0,1.0,1.0);//textureSample(t_diffuse, s_diffuse, in.tex_coords).rgb;
-//
-//    let scanLineWeight = (BRIGHTBOOST - LOWLUMSCAN*(Y - 2.05*YY));
-//    let scanLineWeightB = 1.0 - HILUMSCAN*(YY - 2.8 * YY * Y);
-//
-//    return vec4<f32>(colour.rgb * mix(scanLineWeight*mask, scanLineWeightB, dot(colour.rgb,vec3(MASK_FADE * 0.33333))),1.0);
-//}
\ No newline at end of file
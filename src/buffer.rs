@@ -1,7 +1,7 @@
 use bytemuck::{Pod, Zeroable};
 use wgpu::*;
 use util::DeviceExt;
-use crate::config::*;
+use crate::font::AtlasSet;
 
 
 #[repr(C)]
@@ -30,6 +30,8 @@ pub struct InstanceTileRaw {
     pub uv: [f32; 4],
     pub model: [[f32; 4]; 4],
     pub color: [f32;3],
+    pub bg: [f32;3],
+    pub attr: u32,
 }
 impl InstanceTileRaw {
     pub fn desc<'a>() -> VertexBufferLayout<'a> {
@@ -68,6 +70,16 @@ impl InstanceTileRaw {
                     shader_location: 8,
                     format: VertexFormat::Float32x3,
                 },
+                VertexAttribute {
+                    offset: mem::size_of::<[f32; 23]>() as BufferAddress,
+                    shader_location: 9,
+                    format: VertexFormat::Float32x3,
+                },
+                VertexAttribute {
+                    offset: mem::size_of::<[f32; 26]>() as BufferAddress,
+                    shader_location: 10,
+                    format: VertexFormat::Uint32,
+                },
             ],
         }
     }
@@ -75,34 +87,51 @@ impl InstanceTileRaw {
 
 
 
+/// Per-frame screen description shared with the shaders.
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct ScreenUniform {
+    pub grid_size: [f32; 2],
+    pub _padding: [f32; 2],
+}
+
+impl ScreenUniform {
+    pub fn new(columns: usize, rows: usize) -> Self {
+        ScreenUniform {
+            grid_size: [columns as f32, rows as f32],
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+
 pub struct TileRenderData {
     pub char: u8,
+    pub atlas: u8,
     pub position: [usize; 2],
-    pub color : [f32;3]
+    pub color : [f32;3],
+    pub bg : [f32;3],
+    pub attr : u32
 }
 
 impl TileRenderData {
-    pub fn get_instance_matrix(&self) -> InstanceTileRaw {
-        let char_x = self.char % 16;
-        let char_y = self.char / 16;
-
-        let uv = [
-            (char_x) as f32 * CHR_UV,
-            (char_x+1) as f32 * CHR_UV,
-            (char_y) as f32 * CHR_UV,
-            (char_y+1) as f32 * CHR_UV,
-        ];
+    pub fn get_instance_matrix(&self, columns: usize, rows: usize, atlases: &AtlasSet) -> InstanceTileRaw {
+        let uv = atlases.uv(self.atlas, self.char as u32);
 
-        let x =  self.position[0] as f32 / SCREEN_COLS as f32 * 2.0 - 1.0;
-        let y =  self.position[1] as f32 / SCREEN_ROWS as f32 * - 2.0 + 1.0;
+        let x =  self.position[0] as f32 / columns as f32 * 2.0 - 1.0;
+        let y =  self.position[1] as f32 / rows as f32 * - 2.0 + 1.0;
         let z = 0.0;//-pow( x * x + y * y,5) ;
 
         let model = cgmath::Matrix4::from_translation(cgmath::Vector3 { x, y, z }).into();
         let color = self.color;
+        let bg = self.bg;
+        let attr = self.attr;
         InstanceTileRaw {
             uv,
             model,
-            color
+            color,
+            bg,
+            attr
         }
     }
 }
@@ -117,9 +146,9 @@ pub struct Mesh {
 }
 impl Mesh {
 
-    pub fn new(device : &Device, size : &[f32;2]) -> Self {
-        let x_size = size[0];//2.0 / SCREEN_COLS as f32;
-        let y_size = size[1];//2.0 / SCREEN_ROWS as f32;
+    pub fn new(device : &Device, size : &[f32;2], num_instances: u32) -> Self {
+        let x_size = size[0];//2.0 / columns as f32;
+        let y_size = size[1];//2.0 / rows as f32;
         //region [ Vertex Data ]
         let vertex: [Vertex; 4] = [
             //Front
@@ -170,15 +199,15 @@ impl Mesh {
 
         let num_indices = indices.len() as u32;
 
-        let instances:Vec<InstanceTileRaw> = Vec::new();
-        let instance_buffer = device.create_buffer_init(
-            &util::BufferInitDescriptor {
-                label: Some(format!("Instance Buffer").as_str()),
-                contents: bytemuck::cast_slice(&instances),
+        // Allocated once at full size; contents are streamed in with `queue.write_buffer`.
+        let instance_buffer = device.create_buffer(
+            &BufferDescriptor {
+                label: Some("Instance Buffer"),
+                size: num_instances as BufferAddress * std::mem::size_of::<InstanceTileRaw>() as BufferAddress,
                 usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
             }
         );
-        let num_instances = instances.len() as u32;
 
         Mesh {
             vertex_buffer,
@@ -188,13 +217,6 @@ impl Mesh {
             num_instances,
         }
     }
-
-
-
-    pub fn replace_instance(&mut self, buffer: Buffer , num_instance : u32){
-        self.instance_buffer = buffer;
-        self.num_instances = num_instance;
-    }
 }
 
 
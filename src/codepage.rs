@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use serde::Deserialize;
+
+
+/// Glyphs 0x01 to 0x1f of the IBM PC character set, shared by CP437 and CP850.
+const PC_LOW: &str = "☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼";
+/// Glyph 0x7f of the IBM PC character set.
+const PC_DELETE: char = '⌂';
+/// Glyphs 0x80 to 0xff of code page 437.
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+    ░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀\
+    αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+/// Glyphs 0x80 to 0xff of code page 850.
+const CP850_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜø£Ø×ƒáíóúñÑªº¿®¬½¼¡«»\
+    ░▒▓│┤ÁÂÀ©╣║╗╝¢¥┐└┴┬├─┼ãÃ╚╔╩╦╠═╬¤ðÐÊËÈıÍÎÏ┘┌█▄¦Ì▀\
+    ÓßÔÒõÕµþÞÚÛÙýÝ¯´\u{ad}±‗¾¶§÷¸°¨·¹³²■\u{a0}";
+
+
+/// Built-in character sets a `CodePage` can start from.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BuiltinCodePage {
+    /// The original IBM PC set, with box drawing and Greek letters.
+    #[default]
+    Cp437,
+    /// Multilingual Latin-1, trading some box drawing glyphs for accented letters.
+    Cp850,
+}
+
+/// Maps Unicode characters to glyph indices in the atlas.
+///
+/// In the built-in code pages ASCII maps to itself, and control characters to
+/// the glyph of the same index, so plain text renders the same under both.
+#[derive(Clone, Debug)]
+pub struct CodePage {
+    glyphs: HashMap<char, u8>,
+}
+
+impl CodePage {
+    pub fn builtin(base: BuiltinCodePage) -> Self {
+        let high = match base {
+            BuiltinCodePage::Cp437 => CP437_HIGH,
+            BuiltinCodePage::Cp850 => CP850_HIGH,
+        };
+        let ascii = (0..0x80u8).map(|glyph| (glyph as char, glyph));
+        let graphics = PC_LOW.chars().zip(0x01..).chain([(PC_DELETE, 0x7f)]);
+        let high = high.chars().zip(0x80..=0xff);
+        CodePage { glyphs: ascii.chain(graphics).chain(high).collect() }
+    }
+
+    /// A code page with only the given glyphs. The first glyph listed for a
+    /// character wins, as fonts list their canonical glyph first.
+    pub fn from_glyphs(glyphs: impl IntoIterator<Item = (char, u8)>) -> Self {
+        let mut map = HashMap::new();
+        for (c, glyph) in glyphs {
+            map.entry(c).or_insert(glyph);
+        }
+        CodePage { glyphs: map }
+    }
+
+    /// Adds or replaces the glyphs of the given characters.
+    pub fn with_glyphs(mut self, glyphs: impl IntoIterator<Item = (char, u8)>) -> Self {
+        self.glyphs.extend(glyphs);
+        self
+    }
+
+    /// Glyph index of `c`, if the code page has one.
+    pub fn glyph(&self, c: char) -> Option<u8> {
+        self.glyphs.get(&c).copied()
+    }
+
+    /// Glyph index of `c`, or of `?` when the code page has none.
+    pub fn encode(&self, c: char) -> u8 {
+        self.glyph(c).unwrap_or(b'?')
+    }
+}
+
+impl Default for CodePage {
+    fn default() -> Self {
+        Self::builtin(BuiltinCodePage::default())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cp437_maps_box_drawing_and_latin() {
+        let code_page = CodePage::builtin(BuiltinCodePage::Cp437);
+        let glyphs: Vec<u8> = "═╗é☺⌂A".chars().map(|c| code_page.encode(c)).collect();
+        assert_eq!(glyphs, vec![0xcd, 0xbb, 0x82, 0x01, 0x7f, b'A']);
+        assert_eq!(code_page.glyph('\u{1}'), Some(0x01));
+        for (c, glyph) in CP437_HIGH.chars().zip(0x80..=0xff) {
+            assert_eq!(code_page.glyph(c), Some(glyph), "{c:?}");
+        }
+    }
+
+    #[test]
+    fn cp850_trades_box_drawing_for_accents() {
+        let code_page = CodePage::builtin(BuiltinCodePage::Cp850);
+        assert_eq!(code_page.glyph('═'), Some(0xcd));
+        assert_eq!(code_page.glyph('é'), Some(0x82));
+        assert_eq!(code_page.glyph('Á'), Some(0xb5));
+        assert_eq!(code_page.glyph('╡'), None);
+    }
+
+    #[test]
+    fn builtin_tables_cover_every_glyph_once() {
+        for base in [BuiltinCodePage::Cp437, BuiltinCodePage::Cp850] {
+            let code_page = CodePage::builtin(base);
+            let mut covered = [false; 256];
+            for &glyph in code_page.glyphs.values() {
+                covered[glyph as usize] = true;
+            }
+            assert!(covered.iter().all(|&covered| covered), "{base:?}");
+        }
+    }
+
+    #[test]
+    fn custom_glyphs_override_the_base() {
+        let code_page = CodePage::builtin(BuiltinCodePage::Cp437).with_glyphs([('€', 0xee), ('A', 0x01)]);
+        assert_eq!(code_page.encode('€'), 0xee);
+        assert_eq!(code_page.encode('A'), 0x01);
+        assert_eq!(code_page.encode('\u{2603}'), b'?');
+    }
+}
@@ -1,19 +1,41 @@
 use std::collections::HashMap;
+use std::fmt;
 use serde::Deserialize;
+use toml::Spanned;
+use crate::codepage::{BuiltinCodePage, CodePage};
+use crate::crt::{CrtParams, CrtPreset};
+use crate::font::AtlasLayout;
 use crate::resources::load_string;
 
+const CONFIG_FILE: &str = "game_config.toml";
 
-#[derive(Clone, Copy)]
+
+#[derive(Clone, Copy, Debug)]
 pub struct Tile {
-    pub(crate) char:u8,
-    pub(crate) color:[f32;3]
+    pub char:u8,
+    /// Glyph atlas `char` is taken from, see `Renderer::add_atlas`.
+    pub atlas:u8,
+    pub color:[f32;3],
+    pub bg:[f32;3],
+    /// Combination of the `ATTR_*` flags.
+    pub attr:u32
+}
+
+impl Tile {
+    /// A blank cell without a background, showing the layers below it.
+    pub fn transparent() -> Self {
+        Tile { char: b' ', attr: ATTR_TRANSPARENT, ..Tile::default() }
+    }
 }
 
 impl Default for Tile {
     fn default() -> Self {
         Tile{
             char : 0x00,
-            color: [1.0,1.0,1.0]
+            atlas: 0,
+            color: [1.0,1.0,1.0],
+            bg: [0.0,0.0,0.0],
+            attr: 0
         }
     }
 }
@@ -21,67 +43,408 @@ impl Default for Tile {
 
 #[derive(Debug, Deserialize)]
 pub struct Character {
-    color: usize,
-    char: u8,
-    solid: bool,
+    pub color: usize,
+    /// Palette index of the cell background, black when omitted.
+    #[serde(default)]
+    pub bg: usize,
+    #[serde(default)]
+    pub attr: u32,
+    pub char: u8,
+    /// Glyph atlas `char` is taken from, the text atlas when omitted.
+    #[serde(default)]
+    pub atlas: u8,
+    pub solid: bool,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Options {
     pub screen_size: [u32; 2],
+    #[serde(default = "default_columns")]
+    pub columns: usize,
+    #[serde(default = "default_rows")]
+    pub rows: usize,
+    /// Size of hotkey screenshots relative to the surface.
+    #[serde(default = "default_screenshot_scale")]
+    pub screenshot_scale: f32,
+    /// Scale the letterboxed screen by whole multiples of `screen_size` only.
+    #[serde(default)]
+    pub integer_scaling: bool,
+    /// Built-in CRT look used in place of the `[crt]` table.
+    #[serde(default)]
+    pub preset: Option<CrtPreset>,
+    /// Cell layers drawn on top of each other, see `Console::set_layer`.
+    #[serde(default = "default_layers")]
+    pub layers: usize,
+}
+
+/// One pass of the post-processing chain, from a `[[post]]` entry.
+///
+/// Passes run in order after the tile and bloom passes, each sampling the previous pass's output.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PostPass {
+    /// WGSL file in `res/shader/` with `vs_main` and `fs_main` entry points.
+    pub shader: String,
+    /// Output size relative to the viewport. The last pass always fills the viewport.
+    #[serde(default = "default_pass_scale")]
+    pub scale: f32,
+    /// Up to `POST_UNIFORM_COUNT` values passed to the shader at group(1) binding(3).
+    #[serde(default)]
+    pub uniforms: Vec<f32>,
+}
+
+/// Unicode to glyph mapping for text, from the `[code_page]` table.
+#[derive(Debug, Default, Deserialize)]
+pub struct CodePageConfig {
+    #[serde(default)]
+    pub base: BuiltinCodePage,
+    /// Characters and the glyph index each maps to, overriding `base`.
+    #[serde(default)]
+    pub glyphs: HashMap<char, u8>,
 }
 
+fn default_post_chain() -> Vec<Spanned<PostPass>> {
+    vec![Spanned::new(0..0, PostPass { shader: "post_crt.wgsl".to_string(), scale: 1.0, uniforms: Vec::new() })]
+}
+fn default_pass_scale() -> f32 { 1.0 }
+fn default_columns() -> usize { DEFAULT_COLUMNS }
+fn default_rows() -> usize { DEFAULT_ROWS }
+fn default_layers() -> usize { 1 }
+fn default_screenshot_scale() -> f32 { 1.0 }
+
 #[derive(Debug, Deserialize)]
 pub struct Map {
-    pub world : Vec<String>
+    pub world : Vec<Spanned<String>>
 }
 
+/// Lines at the bottom of the screen reserved for the status message.
+const STATUS_MESSAGE: [&str; 2] = [" ROBIN      HP 34/34    Gold:72", " Paladin Lvl 6        XP:18,390"];
+
 #[derive(Debug, Deserialize)]
 pub struct GameConfig {
-    pub characters : HashMap<char, Character>,
+    pub characters : HashMap<char, Spanned<Character>>,
     pub options : Options,
     pub color:Vec<[f32;3]>,
     pub map:Map,
-
     #[serde(default)]
-    world_map:String,
+    pub crt: CrtParams,
+    #[serde(default = "default_post_chain")]
+    pub post: Vec<Spanned<PostPass>>,
+    /// Layout of the glyph atlas image, a 16x16 grid of 256 glyphs when omitted.
+    #[serde(default)]
+    pub atlas: Option<Spanned<AtlasLayout>>,
+    #[serde(default)]
+    pub code_page: CodePageConfig,
+}
+
+/// Problems found while loading `game_config.toml`.
+///
+/// Lines and columns are 1-based positions in the file.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file couldn't be read.
+    Load { file: String, source: anyhow::Error },
+    /// The file isn't valid TOML or doesn't match the expected layout.
+    Parse { file: String, line: usize, column: usize, message: String },
+    /// A map row uses a glyph with no entry in `[characters]`.
+    UnknownGlyph { file: String, line: usize, column: usize, glyph: char },
+    /// A character refers to a palette entry past the end of `color`.
+    PaletteIndex { file: String, line: usize, column: usize, glyph: char, index: usize, palette_len: usize },
+    /// A map row is not exactly `columns` glyphs wide.
+    RowLength { file: String, line: usize, column: usize, row: usize, expected: usize, found: usize },
+    /// The map and status lines don't fit in `rows`.
+    TooManyRows { file: String, found: usize, max: usize },
+    /// `post` is present but lists no passes.
+    EmptyPostChain { file: String },
+    /// A `[[post]]` entry has a bad scale or too many uniforms.
+    PostPass { file: String, line: usize, column: usize, message: String },
+    /// `columns` or `rows` is zero.
+    EmptyGrid { file: String, columns: usize, rows: usize },
+    /// `layers` is zero.
+    NoLayers { file: String },
+    /// The `[atlas]` table describes no glyphs.
+    EmptyAtlas { file: String, line: usize, column: usize, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Load { file, source } =>
+                write!(f, "{file}: failed to load: {source}"),
+            ConfigError::Parse { file, line, column, message } =>
+                write!(f, "{file}:{line}:{column}: {message}"),
+            ConfigError::UnknownGlyph { file, line, column, glyph } =>
+                write!(f, "{file}:{line}:{column}: map glyph {glyph:?} has no entry in [characters]"),
+            ConfigError::PaletteIndex { file, line, column, glyph, index, palette_len } =>
+                write!(f, "{file}:{line}:{column}: character {glyph:?} uses colour {index} but the palette has {palette_len} entries"),
+            ConfigError::RowLength { file, line, column, row, expected, found } =>
+                write!(f, "{file}:{line}:{column}: map row {row} is {found} glyphs wide, expected {expected}"),
+            ConfigError::TooManyRows { file, found, max } =>
+                write!(f, "{file}: map has {found} rows but only {max} fit above the status lines"),
+            ConfigError::EmptyPostChain { file } =>
+                write!(f, "{file}: post chain needs at least one pass"),
+            ConfigError::PostPass { file, line, column, message } =>
+                write!(f, "{file}:{line}:{column}: post pass {message}"),
+            ConfigError::EmptyGrid { file, columns, rows } =>
+                write!(f, "{file}: options.columns and options.rows must be at least 1, got {columns}x{rows}"),
+            ConfigError::NoLayers { file } =>
+                write!(f, "{file}: options.layers must be at least 1"),
+            ConfigError::EmptyAtlas { file, line, column, message } =>
+                write!(f, "{file}:{line}:{column}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Load { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
 
+/// 1-based line and column of a byte offset into `source`.
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+    (line, column)
 }
 
 impl GameConfig {
-    pub async fn new()->Self{
-        let str = load_string("game_config.toml").await.unwrap();
-        let config : GameConfig = toml::from_str(&str).unwrap();
+    pub async fn new()->Result<Self, ConfigError>{
+        let str = load_string(CONFIG_FILE).await
+            .map_err(|source| ConfigError::Load { file: CONFIG_FILE.to_string(), source })?;
 
         // config.state_message = "ROBIN      HP 34/34    Gold:72\nPaladin Lvl 6        XP:18,390";
-        config
+        Self::parse(&str, CONFIG_FILE)
+    }
+
+    /// Parses and validates a config; `file` is only used in error messages.
+    pub fn parse(source: &str, file: &str) -> Result<Self, ConfigError> {
+        let config : GameConfig = toml::from_str(source).map_err(|err| {
+            let (line, column) = err.span().map_or((1, 1), |span| line_column(source, span.start));
+            ConfigError::Parse { file: file.to_string(), line, column, message: err.message().to_string() }
+        })?;
+        config.validate(source, file)?;
+        Ok(config)
+    }
+
+    fn validate(&self, source: &str, file: &str) -> Result<(), ConfigError> {
+        for (&glyph, character) in &self.characters {
+            for index in [character.get_ref().color, character.get_ref().bg] {
+                if index >= self.color.len() {
+                    let (line, column) = line_column(source, character.span().start);
+                    return Err(ConfigError::PaletteIndex { file: file.to_string(), line, column, glyph, index, palette_len: self.color.len() });
+                }
+            }
+        }
+
+        if self.options.columns == 0 || self.options.rows == 0 {
+            return Err(ConfigError::EmptyGrid { file: file.to_string(), columns: self.options.columns, rows: self.options.rows });
+        }
+
+        if self.options.layers == 0 {
+            return Err(ConfigError::NoLayers { file: file.to_string() });
+        }
+
+        let max_rows = self.options.rows.saturating_sub(STATUS_MESSAGE.len());
+        if self.map.world.len() > max_rows {
+            return Err(ConfigError::TooManyRows { file: file.to_string(), found: self.map.world.len(), max: max_rows });
+        }
+
+        for (row, line) in self.map.world.iter().enumerate() {
+            // Skip the opening quote; map rows are plain strings without escapes.
+            let start = line.span().start + 1;
+            for (offset, glyph) in line.get_ref().char_indices() {
+                if !self.characters.contains_key(&glyph) {
+                    let (line, column) = line_column(source, start + offset);
+                    return Err(ConfigError::UnknownGlyph { file: file.to_string(), line, column, glyph });
+                }
+            }
+
+            let found = line.get_ref().chars().count();
+            if found != self.options.columns {
+                let (line, column) = line_column(source, line.span().start);
+                return Err(ConfigError::RowLength { file: file.to_string(), line, column, row, expected: self.options.columns, found });
+            }
+        }
+
+        if self.post.is_empty() {
+            return Err(ConfigError::EmptyPostChain { file: file.to_string() });
+        }
+        for pass in &self.post {
+            let message = if !(pass.get_ref().scale > 0.0 && pass.get_ref().scale.is_finite()) {
+                format!("{:?} has scale {}, expected a positive number", pass.get_ref().shader, pass.get_ref().scale)
+            } else if pass.get_ref().uniforms.len() > POST_UNIFORM_COUNT {
+                format!("{:?} has {} uniforms, at most {POST_UNIFORM_COUNT} are supported", pass.get_ref().shader, pass.get_ref().uniforms.len())
+            } else {
+                continue;
+            };
+            let (line, column) = line_column(source, pass.span().start);
+            return Err(ConfigError::PostPass { file: file.to_string(), line, column, message });
+        }
+
+        if let Some(atlas) = &self.atlas {
+            if let Err(err) = atlas.get_ref().check_not_empty() {
+                let (line, column) = line_column(source, atlas.span().start);
+                return Err(ConfigError::EmptyAtlas { file: file.to_string(), line, column, message: err.to_string() });
+            }
+        }
+        Ok(())
+    }
+
+    /// CRT parameters of the configured preset, or the `[crt]` table without one.
+    pub fn crt_params(&self) -> CrtParams {
+        self.options.preset.map_or(self.crt, CrtPreset::params)
     }
 
-    pub fn get_map(&self)->Vec<Tile>{
-        let mut tile_set = self.map.world.iter().flat_map(|line|{
-           line.chars()
+    /// Code page for console text: the `base` table with `glyphs` applied on top.
+    pub fn code_page(&self) -> CodePage {
+        CodePage::builtin(self.code_page.base).with_glyphs(self.code_page.glyphs.iter().map(|(&c, &glyph)| (c, glyph)))
+    }
+
+    /// Map rows followed by the status lines, one `Vec` per screen row.
+    pub fn get_map(&self)->Vec<Vec<Tile>>{
+        let mut tile_set = self.map.world.iter().map(|line|{
+           line.get_ref().chars()
                .filter_map(|c|{ self.characters.get(&c) })
                .map(|c| {
+                   let c = c.get_ref();
                    Tile {
                        char : c.char,
-                       color : self.color[c.color]
+                       atlas : c.atlas,
+                       color : self.color[c.color],
+                       bg : self.color[c.bg],
+                       attr : c.attr
                    }
                })
                .collect::<Vec<_>>()
         }).collect::<Vec<_>>();
 
 
-        let status_message = " ROBIN      HP 34/34    Gold:72  Paladin Lvl 6        XP:18,390";
-        for char in status_message.chars() {
-            tile_set.push(Tile{
-                char: char as u8,
-                color : [1.0,1.0,1.0]
-            })
+        let code_page = self.code_page();
+        for line in STATUS_MESSAGE {
+            tile_set.push(line.chars().map(|char| Tile{
+                char: code_page.encode(char),
+                atlas: 0,
+                color : [1.0,1.0,1.0],
+                bg : [0.0,0.0,0.0],
+                attr : 0
+            }).collect());
         }
-        return tile_set;
+        tile_set
     }
 }
 
-pub const SCREEN_ROWS: usize =24;
-pub const SCREEN_COLS: usize =32;
-pub const CHR_UV:f32 = 0.0625;
\ No newline at end of file
+// Text attribute flags, mirrored in colour_tile.wgsl.
+pub const ATTR_BLINK: u32 = 1 << 0;
+pub const ATTR_UNDERLINE: u32 = 1 << 1;
+pub const ATTR_REVERSE: u32 = 1 << 2;
+pub const ATTR_BOLD: u32 = 1 << 3;
+pub const ATTR_DIM: u32 = 1 << 4;
+/// Skip the cell background so lower layers show around the glyph.
+pub const ATTR_TRANSPARENT: u32 = 1 << 5;
+
+pub const DEFAULT_ROWS: usize =24;
+pub const DEFAULT_COLUMNS: usize =32;
+/// Uniform floats available to each post pass, as `array<vec4<f32>, 4>` in WGSL.
+pub const POST_UNIFORM_COUNT: usize = 16;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"color = [[0.0, 0.0, 0.0], [1.0, 1.0, 1.0]]
+
+[characters]
+"." = { color = 1, char = 0x2e, solid = false }
+"=" = { color = 1, char = 0x23, solid = true }
+
+[options]
+screen_size = [320, 240]
+columns = 4
+rows = 4
+
+[map]
+world = [
+    "=..=",
+    "====",
+]
+"#;
+
+    fn parse_with(from: &str, to: &str) -> Result<GameConfig, ConfigError> {
+        assert!(CONFIG.contains(from), "{from:?} is not in the test config");
+        GameConfig::parse(&CONFIG.replacen(from, to, 1), "test.toml")
+    }
+
+    #[test]
+    fn parses_a_valid_config() {
+        let config = GameConfig::parse(CONFIG, "test.toml").unwrap();
+        let map = config.get_map();
+        assert_eq!(map.len(), 2 + STATUS_MESSAGE.len());
+        assert_eq!(map[0][0].char, b'#');
+        assert_eq!(map[0][1].char, b'.');
+    }
+
+    #[test]
+    fn reports_toml_errors_with_their_position() {
+        let err = parse_with("columns = 4", "columns = \"x\"").unwrap_err();
+        assert!(matches!(err, ConfigError::Parse { line: 9, column: 11, .. }), "{err}");
+        assert!(err.to_string().starts_with("test.toml:9:11: "), "{err}");
+    }
+
+    #[test]
+    fn reports_unknown_glyphs_at_the_glyph() {
+        let err = parse_with("\"=..=\"", "\"=.x=\"").unwrap_err();
+        assert!(matches!(err, ConfigError::UnknownGlyph { line: 14, column: 8, glyph: 'x', .. }), "{err}");
+    }
+
+    #[test]
+    fn reports_palette_indices_past_the_palette() {
+        let err = parse_with("\".\" = { color = 1", "\".\" = { color = 5").unwrap_err();
+        assert!(matches!(err, ConfigError::PaletteIndex { line: 4, column: 7, glyph: '.', index: 5, palette_len: 2, .. }), "{err}");
+    }
+
+    #[test]
+    fn reports_rows_of_the_wrong_width() {
+        let err = parse_with("\"====\"", "\"===\"").unwrap_err();
+        assert!(matches!(err, ConfigError::RowLength { line: 15, column: 5, row: 1, expected: 4, found: 3, .. }), "{err}");
+    }
+
+    #[test]
+    fn reports_maps_overlapping_the_status_lines() {
+        let err = parse_with("\"====\",", "\"====\",\n    \"====\",").unwrap_err();
+        assert!(matches!(err, ConfigError::TooManyRows { found: 3, max: 2, .. }), "{err}");
+    }
+
+    #[test]
+    fn reports_bad_post_passes() {
+        let err = parse_with("color = [", "post = []\ncolor = [").unwrap_err();
+        assert!(matches!(err, ConfigError::EmptyPostChain { .. }), "{err}");
+
+        let err = GameConfig::parse(&format!("{CONFIG}\n[[post]]\nshader = \"post_crt.wgsl\"\nscale = 0.0\n"), "test.toml").unwrap_err();
+        assert!(matches!(err, ConfigError::PostPass { line: 18, column: 1, .. }), "{err}");
+    }
+
+    #[test]
+    fn reports_empty_grids() {
+        let err = parse_with("columns = 4", "columns = 0").unwrap_err();
+        assert!(matches!(err, ConfigError::EmptyGrid { columns: 0, rows: 4, .. }), "{err}");
+        let err = parse_with("rows = 4", "rows = 0").unwrap_err();
+        assert!(matches!(err, ConfigError::EmptyGrid { columns: 4, rows: 0, .. }), "{err}");
+    }
+
+    #[test]
+    fn reports_zero_layers() {
+        let err = parse_with("rows = 4", "rows = 4\nlayers = 0").unwrap_err();
+        assert!(matches!(err, ConfigError::NoLayers { .. }), "{err}");
+    }
+
+    #[test]
+    fn reports_empty_atlases() {
+        let err = GameConfig::parse(&format!("{CONFIG}\n[atlas]\nglyph_width = 8\nglyph_height = 8\ncolumns = 16\nglyph_count = 0\n"), "test.toml").unwrap_err();
+        assert!(matches!(err, ConfigError::EmptyAtlas { line: 18, column: 1, .. }), "{err}");
+        assert_eq!(err.to_string(), "test.toml:18:1: atlas needs at least one column and one glyph");
+    }
+}
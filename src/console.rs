@@ -0,0 +1,373 @@
+use std::ops::Range;
+use crate::codepage::CodePage;
+use crate::config::{Tile, ATTR_TRANSPARENT};
+
+
+/// Text-mode view of the screen grid.
+///
+/// Every write marks the touched cells dirty so the renderer re-uploads only
+/// those instances on the next frame. Writes outside the grid are clipped.
+/// Text is mapped to glyphs through a `CodePage`, CP437 unless changed.
+///
+/// The grid is a stack of layers drawn bottom to top. Writes go to the layer
+/// picked with `set_layer`, layer 0 by default. Cells of the layers above 0
+/// start out as `Tile::transparent`, so the layers below show through until a
+/// background is set on them.
+pub struct Console {
+    columns: usize,
+    rows: usize,
+    layers: usize,
+    layer: usize,
+    /// Cells of every layer, layer by layer, each row-major.
+    tiles: Vec<Tile>,
+    dirty: Vec<bool>,
+    any_dirty: bool,
+    code_page: CodePage,
+}
+
+impl Console {
+    pub fn new(columns: usize, rows: usize) -> Self {
+        Self::with_layers(columns, rows, 1)
+    }
+
+    pub fn with_layers(columns: usize, rows: usize, layers: usize) -> Self {
+        let mut tiles = Vec::with_capacity(columns * rows * layers);
+        for layer in 0..layers {
+            tiles.extend(std::iter::repeat_n(Self::empty_tile(layer), columns * rows));
+        }
+        Console {
+            columns,
+            rows,
+            layers,
+            layer: 0,
+            dirty: vec![true; tiles.len()],
+            tiles,
+            any_dirty: true,
+            code_page: CodePage::default(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.columns
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows
+    }
+
+    pub fn layer_count(&self) -> usize {
+        self.layers
+    }
+
+    pub fn layer(&self) -> usize {
+        self.layer
+    }
+
+    /// Selects the layer later writes and `get` use. Writes to a layer past
+    /// `layer_count` are clipped like writes outside the grid.
+    pub fn set_layer(&mut self, layer: usize) {
+        self.layer = layer;
+    }
+
+    pub fn code_page(&self) -> &CodePage {
+        &self.code_page
+    }
+
+    /// Changes how later `print` calls map characters to glyphs.
+    pub fn set_code_page(&mut self, code_page: CodePage) {
+        self.code_page = code_page;
+    }
+
+    /// Cells of every layer, bottom layer first.
+    pub fn tiles(&self) -> &[Tile] {
+        &self.tiles
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<&Tile> {
+        self.index(x, y).map(|i| &self.tiles[i])
+    }
+
+    pub fn set_tile(&mut self, x: usize, y: usize, tile: Tile) {
+        if let Some(i) = self.index(x, y) {
+            self.tiles[i] = tile;
+            self.mark_dirty(i);
+        }
+    }
+
+    /// Sets the glyph and foreground of a cell, keeping its background.
+    /// Characters always come from the text atlas, atlas 0.
+    pub fn put_char(&mut self, x: usize, y: usize, ch: u8, fg: [f32; 3]) {
+        self.put_glyph(x, y, 0, ch, fg);
+    }
+
+    /// Like `put_char`, with a glyph from any atlas registered with the renderer.
+    pub fn put_glyph(&mut self, x: usize, y: usize, atlas: u8, glyph: u8, fg: [f32; 3]) {
+        self.update(x, y, |tile| {
+            tile.char = glyph;
+            tile.atlas = atlas;
+            tile.color = fg;
+        });
+    }
+
+    /// Sets the glyph and both colours of a cell, making it opaque.
+    pub fn put_char_bg(&mut self, x: usize, y: usize, ch: u8, fg: [f32; 3], bg: [f32; 3]) {
+        self.update(x, y, |tile| {
+            tile.char = ch;
+            tile.atlas = 0;
+            tile.color = fg;
+            tile.bg = bg;
+            tile.attr &= !ATTR_TRANSPARENT;
+        });
+    }
+
+    /// Sets the background of a cell, making it opaque.
+    pub fn set_bg(&mut self, x: usize, y: usize, bg: [f32; 3]) {
+        self.update(x, y, |tile| {
+            tile.bg = bg;
+            tile.attr &= !ATTR_TRANSPARENT;
+        });
+    }
+
+    /// Replaces the `ATTR_*` flags of a cell. A transparent cell stays
+    /// transparent; set a background to make it opaque.
+    pub fn set_attr(&mut self, x: usize, y: usize, attr: u32) {
+        self.update(x, y, |tile| tile.attr = attr | (tile.attr & ATTR_TRANSPARENT));
+    }
+
+    /// Writes `text` left to right starting at `(x, y)`, clipped at the right edge.
+    /// Characters missing from the code page are drawn as `?`.
+    pub fn print(&mut self, x: usize, y: usize, text: &str, fg: [f32; 3]) {
+        for (offset, c) in text.chars().enumerate() {
+            if x + offset >= self.width() {
+                break;
+            }
+            let ch = self.code_page.encode(c);
+            self.put_char(x + offset, y, ch, fg);
+        }
+    }
+
+    pub fn print_bg(&mut self, x: usize, y: usize, text: &str, fg: [f32; 3], bg: [f32; 3]) {
+        for (offset, c) in text.chars().enumerate() {
+            if x + offset >= self.width() {
+                break;
+            }
+            let ch = self.code_page.encode(c);
+            self.put_char_bg(x + offset, y, ch, fg, bg);
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, ch: u8, fg: [f32; 3]) {
+        for row in y..(y + height).min(self.height()) {
+            for col in x..(x + width).min(self.width()) {
+                self.put_char(col, row, ch, fg);
+            }
+        }
+    }
+
+    /// Changes the background of a rectangle without touching its glyphs, making it opaque.
+    pub fn fill_bg(&mut self, x: usize, y: usize, width: usize, height: usize, bg: [f32; 3]) {
+        for row in y..(y + height).min(self.height()) {
+            for col in x..(x + width).min(self.width()) {
+                self.set_bg(col, row, bg);
+            }
+        }
+    }
+
+    pub fn fill_attr(&mut self, x: usize, y: usize, width: usize, height: usize, attr: u32) {
+        for row in y..(y + height).min(self.height()) {
+            for col in x..(x + width).min(self.width()) {
+                self.set_attr(col, row, attr);
+            }
+        }
+    }
+
+    /// Resets a rectangle of the current layer to empty cells.
+    pub fn clear_rect(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        let empty = Self::empty_tile(self.layer);
+        for row in y..(y + height).min(self.height()) {
+            for col in x..(x + width).min(self.width()) {
+                self.set_tile(col, row, empty);
+            }
+        }
+    }
+
+    /// Resets every layer to empty cells.
+    pub fn clear(&mut self) {
+        let cells = self.columns * self.rows;
+        for layer in 0..self.layers {
+            self.tiles[layer * cells..(layer + 1) * cells].fill(Self::empty_tile(layer));
+        }
+        self.mark_all_dirty();
+    }
+
+    /// Copies each row of `rows` into the current layer line by line, starting at the top left.
+    pub fn load_rows(&mut self, rows: &[Vec<Tile>]) {
+        for (y, row) in rows.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                self.set_tile(x, y, *tile);
+            }
+        }
+    }
+
+    /// Returns the dirty cells as contiguous index ranges and clears the dirty flags.
+    pub(crate) fn take_dirty_ranges(&mut self) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        if !self.any_dirty {
+            return ranges;
+        }
+
+        let mut start = None;
+        for (i, dirty) in self.dirty.iter_mut().enumerate() {
+            match (*dirty, start) {
+                (true, None) => start = Some(i),
+                (false, Some(s)) => {
+                    ranges.push(s..i);
+                    start = None;
+                }
+                _ => {}
+            }
+            *dirty = false;
+        }
+        if let Some(s) = start {
+            ranges.push(s..self.dirty.len());
+        }
+
+        self.any_dirty = false;
+        ranges
+    }
+
+    fn update(&mut self, x: usize, y: usize, f: impl FnOnce(&mut Tile)) {
+        if let Some(i) = self.index(x, y) {
+            f(&mut self.tiles[i]);
+            self.mark_dirty(i);
+        }
+    }
+
+    fn mark_dirty(&mut self, index: usize) {
+        self.dirty[index] = true;
+        self.any_dirty = true;
+    }
+
+    pub(crate) fn mark_all_dirty(&mut self) {
+        self.dirty.fill(true);
+        self.any_dirty = true;
+    }
+
+    /// Blank black cells on layer 0, see-through cells above it.
+    fn empty_tile(layer: usize) -> Tile {
+        if layer == 0 { Tile::default() } else { Tile::transparent() }
+    }
+
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        if x < self.width() && y < self.height() && self.layer < self.layers {
+            Some((self.layer * self.rows + y) * self.width() + x)
+        } else {
+            None
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ATTR_BLINK;
+
+    #[test]
+    fn dirty_cells_coalesce_into_ranges() {
+        let mut console = Console::new(4, 3);
+        assert_eq!(console.take_dirty_ranges(), vec![0..12]);
+        assert!(console.take_dirty_ranges().is_empty());
+
+        console.put_char(1, 0, b'a', [1.0; 3]);
+        console.put_char(2, 0, b'b', [1.0; 3]);
+        console.put_char(3, 1, b'c', [1.0; 3]);
+        console.put_char(0, 2, b'd', [1.0; 3]);
+        // (3, 1) and (0, 2) are neighbours in row-major order.
+        assert_eq!(console.take_dirty_ranges(), vec![1..3, 7..9]);
+        assert!(console.take_dirty_ranges().is_empty());
+    }
+
+    #[test]
+    fn clipped_writes_mark_nothing_dirty() {
+        let mut console = Console::new(4, 3);
+        console.take_dirty_ranges();
+        console.print(2, 1, "xyz", [1.0; 3]);
+        console.put_char(9, 9, b'a', [1.0; 3]);
+        assert_eq!(console.take_dirty_ranges(), vec![6..8]);
+        assert_eq!(console.get(3, 1).unwrap().char, b'y');
+    }
+
+    #[test]
+    fn layers_are_stored_after_each_other() {
+        let mut console = Console::with_layers(2, 2, 3);
+        assert_eq!(console.tiles().len(), 12);
+        assert_eq!(console.tiles()[3].attr, 0);
+        assert_eq!(console.tiles()[4].attr, ATTR_TRANSPARENT);
+        console.take_dirty_ranges();
+
+        console.set_layer(2);
+        console.put_char(1, 1, b'a', [1.0; 3]);
+        assert_eq!(console.take_dirty_ranges(), vec![11..12]);
+        assert_eq!(console.tiles()[11].char, b'a');
+        assert_eq!(console.tiles()[11].attr, ATTR_TRANSPARENT);
+        assert_eq!(console.get(1, 1).unwrap().char, b'a');
+
+        console.set_layer(0);
+        assert_eq!(console.get(1, 1).unwrap().char, 0);
+    }
+
+    #[test]
+    fn writes_to_missing_layers_are_clipped() {
+        let mut console = Console::with_layers(2, 2, 2);
+        console.take_dirty_ranges();
+        console.set_layer(2);
+        console.put_char(0, 0, b'a', [1.0; 3]);
+        assert!(console.get(0, 0).is_none());
+        assert!(console.take_dirty_ranges().is_empty());
+    }
+
+    #[test]
+    fn clearing_restores_transparent_upper_layers() {
+        let mut console = Console::with_layers(2, 1, 2);
+        console.set_layer(1);
+        console.set_tile(0, 0, Tile { char: b'x', ..Tile::default() });
+        console.clear_rect(0, 0, 1, 1);
+        assert_eq!(console.get(0, 0).unwrap().attr, ATTR_TRANSPARENT);
+
+        console.set_tile(1, 0, Tile { char: b'y', ..Tile::default() });
+        console.clear();
+        assert!(console.tiles()[2..].iter().all(|tile| tile.attr == ATTR_TRANSPARENT));
+        assert!(console.tiles()[..2].iter().all(|tile| tile.attr == 0));
+    }
+
+    #[test]
+    fn attributes_keep_upper_layer_cells_transparent() {
+        let mut console = Console::with_layers(3, 1, 2);
+        console.set_layer(1);
+        console.set_attr(0, 0, ATTR_BLINK);
+        console.fill_attr(1, 0, 2, 1, ATTR_BLINK);
+        assert!(console.tiles()[3..].iter().all(|tile| tile.attr == ATTR_BLINK | ATTR_TRANSPARENT));
+
+        console.set_layer(0);
+        console.set_attr(0, 0, ATTR_BLINK);
+        assert_eq!(console.get(0, 0).unwrap().attr, ATTR_BLINK);
+    }
+
+    #[test]
+    fn backgrounds_make_upper_layer_cells_opaque() {
+        let mut console = Console::with_layers(3, 2, 2);
+        console.set_layer(1);
+        console.put_char_bg(0, 0, b'a', [1.0; 3], [0.0, 0.0, 1.0]);
+        console.print_bg(1, 0, "b", [1.0; 3], [0.0, 0.0, 1.0]);
+        console.fill_bg(0, 1, 2, 1, [0.0, 0.0, 1.0]);
+        console.set_attr(0, 0, ATTR_BLINK);
+
+        assert_eq!(console.get(0, 0).unwrap().attr, ATTR_BLINK);
+        assert_eq!(console.get(1, 0).unwrap().attr, 0);
+        assert_eq!(console.get(0, 1).unwrap().attr, 0);
+        assert_eq!(console.get(1, 1).unwrap().attr, 0);
+        assert_eq!(console.get(2, 0).unwrap().attr, ATTR_TRANSPARENT);
+        assert_eq!(console.get(2, 1).unwrap().attr, ATTR_TRANSPARENT);
+    }
+}
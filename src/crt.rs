@@ -0,0 +1,218 @@
+use bytemuck::{Pod, Zeroable};
+use serde::{Deserialize, Deserializer};
+
+
+/// Phosphor layout the CRT pass draws over the image, picked with `mask` in the `[crt]` table.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PhosphorMask {
+    Off = 0,
+    /// Continuous vertical RGB stripes, as on Trinitron tubes.
+    ApertureGrille = 1,
+    /// Staggered RGB dot triads.
+    ShadowMask = 2,
+    /// RGB stripes broken into slots on alternating rows.
+    SlotMask = 3,
+}
+
+
+/// Tunables of the CRT post pass, read from the `[crt]` table of game_config.toml.
+///
+/// The layout matches `CrtParams` in crt_params.wgsl, padded to 16 bytes for uniform buffers.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Deserialize)]
+#[serde(default)]
+pub struct CrtParams {
+    /// Amount of coloured noise added on top of the image.
+    pub noise_strength: f32,
+    /// How much the noise darkens the image.
+    pub noise_darkening: f32,
+    /// Scanlines per text row.
+    pub scanline_density: f32,
+    /// Darkness of the gaps between scanlines, 0 disables them.
+    pub scanline_depth: f32,
+    /// Brightness of the glow along the tube edges.
+    pub border_glow: f32,
+    /// Barrel distortion of the tube, 0 keeps the screen flat.
+    pub curvature: f32,
+    /// Radius of the rounded tube corners, relative to half the screen.
+    pub corner_radius: f32,
+    /// Darkening towards the edges, 0 disables it.
+    pub vignette: f32,
+    /// Share of the previous frame each phosphor channel keeps, 0 disables afterglow.
+    pub persistence: [f32; 3],
+    /// Brightness above which glyphs start to bloom.
+    pub bloom_threshold: f32,
+    /// Spread of the bloom blur, in texels of the smallest bloom level per tap.
+    pub bloom_radius: f32,
+    /// Strength of the bloom added back onto the image, 0 disables the bloom passes.
+    pub bloom_intensity: f32,
+    /// A `PhosphorMask` as `u32`; written as its snake_case name in game_config.toml.
+    #[serde(deserialize_with = "deserialize_mask")]
+    pub mask: u32,
+    /// Darkening between phosphors, 0 hides the mask.
+    pub mask_strength: f32,
+    /// Output pixels per phosphor.
+    pub mask_scale: f32,
+    /// Non-zero to light only every other scanline, alternating each frame;
+    /// written as a bool in game_config.toml.
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub interlace: u32,
+    /// How much of the previous field stays on the unlit scanlines, 1 weaves both fields together.
+    pub deinterlace: f32,
+    #[serde(skip)]
+    pub _padding: f32,
+    /// Colour the image is multiplied with, e.g. the phosphor colour of a monochrome tube.
+    pub tint: [f32; 3],
+    /// Blend towards luminance before tinting, 1 for a monochrome tube.
+    pub monochrome: f32,
+}
+
+impl Default for CrtParams {
+    fn default() -> Self {
+        CrtParams {
+            noise_strength: 0.025,
+            noise_darkening: 0.25,
+            scanline_density: 8.0,
+            scanline_depth: 0.7,
+            border_glow: 0.02,
+            curvature: 0.0,
+            corner_radius: 0.0,
+            vignette: 0.0,
+            persistence: [0.0; 3],
+            bloom_threshold: 0.6,
+            bloom_radius: 1.0,
+            bloom_intensity: 0.0,
+            mask: PhosphorMask::Off as u32,
+            mask_strength: 0.3,
+            mask_scale: 1.0,
+            interlace: 0,
+            deinterlace: 0.0,
+            _padding: 0.0,
+            tint: [1.0; 3],
+            monochrome: 0.0,
+        }
+    }
+}
+
+fn deserialize_mask<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    PhosphorMask::deserialize(deserializer).map(|mask| mask as u32)
+}
+
+fn deserialize_flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    bool::deserialize(deserializer).map(u32::from)
+}
+
+/// Named bundles of `CrtParams`, picked with `preset` in `[options]` or `Renderer::set_preset`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrtPreset {
+    /// Sharp image with only faint scanlines.
+    Clean,
+    /// Curved, noisy shadow mask set with some afterglow.
+    ConsumerTv,
+    /// Flat-ish aperture grille monitor with deep scanlines and strong bloom.
+    Arcade,
+    GreenMonochrome,
+    AmberTerminal,
+}
+
+impl CrtPreset {
+    pub const ALL: [CrtPreset; 5] = [
+        CrtPreset::Clean,
+        CrtPreset::ConsumerTv,
+        CrtPreset::Arcade,
+        CrtPreset::GreenMonochrome,
+        CrtPreset::AmberTerminal,
+    ];
+
+    /// The preset after this one in `ALL`, wrapping around.
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&preset| preset == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    pub fn params(self) -> CrtParams {
+        let base = CrtParams::default();
+        match self {
+            CrtPreset::Clean => CrtParams {
+                noise_strength: 0.0,
+                noise_darkening: 0.0,
+                scanline_depth: 0.2,
+                border_glow: 0.0,
+                ..base
+            },
+            CrtPreset::ConsumerTv => CrtParams {
+                noise_strength: 0.04,
+                noise_darkening: 0.3,
+                scanline_depth: 0.5,
+                curvature: 0.1,
+                corner_radius: 0.08,
+                vignette: 0.35,
+                persistence: [0.3, 0.35, 0.25],
+                bloom_intensity: 0.4,
+                mask: PhosphorMask::ShadowMask as u32,
+                mask_strength: 0.25,
+                ..base
+            },
+            CrtPreset::Arcade => CrtParams {
+                noise_strength: 0.015,
+                scanline_depth: 0.8,
+                curvature: 0.05,
+                corner_radius: 0.03,
+                vignette: 0.2,
+                bloom_threshold: 0.5,
+                bloom_radius: 2.0,
+                bloom_intensity: 0.6,
+                mask: PhosphorMask::ApertureGrille as u32,
+                mask_strength: 0.3,
+                ..base
+            },
+            CrtPreset::GreenMonochrome => CrtParams {
+                scanline_depth: 0.6,
+                curvature: 0.06,
+                corner_radius: 0.05,
+                vignette: 0.3,
+                persistence: [0.6; 3],
+                bloom_intensity: 0.5,
+                tint: [0.25, 1.0, 0.35],
+                monochrome: 1.0,
+                ..base
+            },
+            CrtPreset::AmberTerminal => CrtParams {
+                scanline_depth: 0.6,
+                curvature: 0.06,
+                corner_radius: 0.05,
+                vignette: 0.3,
+                persistence: [0.5; 3],
+                bloom_intensity: 0.4,
+                tint: [1.0, 0.7, 0.15],
+                monochrome: 1.0,
+                ..base
+            },
+        }
+    }
+}
+
+impl CrtParams {
+    /// Maps a point on the output (0..1, top left origin) to the point of the
+    /// flat screen image shown there, the same way `distort` in post_crt.wgsl does.
+    ///
+    /// The result lies outside 0..1 for points off the tube face.
+    pub fn distort(&self, uv: [f32; 2]) -> [f32; 2] {
+        let x = uv[0] * 2.0 - 1.0;
+        let y = uv[1] * 2.0 - 1.0;
+        let scale = (1.0 + self.curvature * (x * x + y * y)) / (1.0 + self.curvature);
+        [x * scale * 0.5 + 0.5, y * scale * 0.5 + 0.5]
+    }
+
+    /// Whether a point of the flat screen image lies inside the rounded tube face,
+    /// the same way `tube_mask` in post_crt.wgsl does.
+    pub fn in_tube(&self, uv: [f32; 2]) -> bool {
+        let radius = self.corner_radius.max(0.0001);
+        let corner_x = ((uv[0] * 2.0 - 1.0).abs() - (1.0 - radius)).max(0.0);
+        let corner_y = ((uv[1] * 2.0 - 1.0).abs() - (1.0 - radius)).max(0.0);
+        corner_x.hypot(corner_y) < radius
+    }
+}
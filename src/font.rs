@@ -0,0 +1,587 @@
+use anyhow::{anyhow, bail, ensure, Context};
+use image::{Rgba, RgbaImage};
+use serde::Deserialize;
+use crate::codepage::CodePage;
+
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TAB: u8 = 0x02;
+const PSF1_SEPARATOR: u16 = 0xffff;
+const PSF1_START_SEQ: u16 = 0xfffe;
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xff;
+const PSF2_START_SEQ: u8 = 0xfe;
+/// Tiles address glyphs with a `u8`, so BDF glyphs with higher encodings are skipped.
+const BDF_MAX_GLYPHS: usize = 256;
+/// Glyphs per row of generated atlases.
+const ATLAS_COLUMNS: u32 = 16;
+
+
+/// Where glyphs sit in an atlas texture: a grid of equally sized cells, filled
+/// row by row from the top left. Read from the `[atlas]` table of game_config.toml.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct AtlasLayout {
+    pub glyph_width: u32,
+    pub glyph_height: u32,
+    /// Glyphs per atlas row.
+    pub columns: u32,
+    pub glyph_count: u32,
+    /// Empty pixels between neighbouring glyph cells.
+    #[serde(default)]
+    pub padding: u32,
+}
+
+impl AtlasLayout {
+    /// A 16x16 grid of 256 glyphs covering a `width` x `height` texture, like res/chr.png.
+    pub fn grid_16x16(width: u32, height: u32) -> Self {
+        AtlasLayout { glyph_width: width / 16, glyph_height: height / 16, columns: 16, glyph_count: 256, padding: 0 }
+    }
+
+    pub fn rows(&self) -> u32 {
+        self.glyph_count.div_ceil(self.columns)
+    }
+
+    /// Smallest texture size in pixels holding every glyph.
+    pub fn size(&self) -> (u32, u32) {
+        let rows = self.rows();
+        (
+            self.columns * self.glyph_width + self.columns.saturating_sub(1) * self.padding,
+            rows * self.glyph_height + rows.saturating_sub(1) * self.padding,
+        )
+    }
+
+    /// Checks that the layout has a glyph size, a column and a glyph.
+    pub fn check_not_empty(&self) -> anyhow::Result<()> {
+        ensure!(self.glyph_width > 0 && self.glyph_height > 0, "glyphs of {}x{} are empty", self.glyph_width, self.glyph_height);
+        ensure!(self.columns > 0 && self.glyph_count > 0, "atlas needs at least one column and one glyph");
+        Ok(())
+    }
+
+    /// Checks that the layout is non-empty and fits in a `width` x `height` texture.
+    pub fn validate(&self, width: u32, height: u32) -> anyhow::Result<()> {
+        self.check_not_empty()?;
+        let (needed_width, needed_height) = self.size();
+        ensure!(width >= needed_width && height >= needed_height,
+            "{} glyphs of {}x{} in {} columns need a {needed_width}x{needed_height} texture, the image is {width}x{height}",
+            self.glyph_count, self.glyph_width, self.glyph_height, self.columns);
+        Ok(())
+    }
+
+    /// Texture coordinates of `glyph` for a layout starting at pixel `origin` of a
+    /// texture of `texture_size` pixels, as `[left, right, top, bottom]`.
+    /// Glyphs past `glyph_count` fall back to glyph 0.
+    pub fn uv(&self, glyph: u32, origin: [u32; 2], texture_size: [u32; 2]) -> [f32; 4] {
+        let glyph = if glyph < self.glyph_count { glyph } else { 0 };
+        let x = (origin[0] + glyph % self.columns * (self.glyph_width + self.padding)) as f32;
+        let y = (origin[1] + glyph / self.columns * (self.glyph_height + self.padding)) as f32;
+        let width = texture_size[0] as f32;
+        let height = texture_size[1] as f32;
+        [
+            x / width,
+            (x + self.glyph_width as f32) / width,
+            y / height,
+            (y + self.glyph_height as f32) / height,
+        ]
+    }
+}
+
+
+/// Glyph atlases packed top to bottom into one texture, so a single draw can
+/// mix them without texture arrays, which WebGL2 can't sample per instance.
+#[derive(Default)]
+pub(crate) struct AtlasSet {
+    atlases: Vec<(RgbaImage, AtlasLayout)>,
+}
+
+impl AtlasSet {
+    pub fn len(&self) -> usize {
+        self.atlases.len()
+    }
+
+    /// Replaces atlas `index`, or adds it when `index` is one past the last atlas.
+    /// Fails if the packed texture would be larger than `max_dimension` on a side.
+    pub fn set(&mut self, index: usize, image: RgbaImage, layout: AtlasLayout, max_dimension: u32) -> anyhow::Result<()> {
+        let others = self.atlases.iter().enumerate().filter(|&(other, _)| other != index).map(|(_, (image, _))| image);
+        let width = others.clone().map(|image| image.width()).fold(image.width(), u32::max);
+        let height = others.map(|image| image.height()).sum::<u32>() + image.height();
+        ensure!(width <= max_dimension && height <= max_dimension,
+            "packed atlases of {width}x{height} exceed the {max_dimension} pixel texture limit");
+
+        if index < self.atlases.len() {
+            self.atlases[index] = (image, layout);
+        } else {
+            assert_eq!(index, self.atlases.len(), "atlas {index} would leave a gap");
+            self.atlases.push((image, layout));
+        }
+        Ok(())
+    }
+
+    /// Size of the packed texture in pixels.
+    pub fn size(&self) -> [u32; 2] {
+        self.atlases.iter().fold([0, 0], |[width, height], (image, _)| [width.max(image.width()), height + image.height()])
+    }
+
+    /// All atlases copied into one image, each below the previous.
+    pub fn pack(&self) -> RgbaImage {
+        let [width, height] = self.size();
+        let mut packed = RgbaImage::new(width, height);
+        let mut y = 0;
+        for (image, _) in &self.atlases {
+            image::imageops::replace(&mut packed, image, 0, y as i64);
+            y += image.height();
+        }
+        packed
+    }
+
+    /// Texture coordinates of `glyph` of atlas `atlas` in the packed texture.
+    /// Unknown atlases fall back to atlas 0.
+    pub fn uv(&self, atlas: u8, glyph: u32) -> [f32; 4] {
+        let index = if (atlas as usize) < self.atlases.len() { atlas as usize } else { 0 };
+        let Some((_, layout)) = self.atlases.get(index) else { return [0.0; 4] };
+        let origin_y = self.atlases[..index].iter().map(|(image, _)| image.height()).sum();
+        layout.uv(glyph, [0, origin_y], self.size())
+    }
+}
+
+
+/// Monochrome bitmap font, as found in Linux console (PSF) and X11 (BDF) font files.
+pub struct BitmapFont {
+    width: u32,
+    height: u32,
+    /// Row-major coverage of each glyph, `width * height` entries per glyph.
+    glyphs: Vec<Vec<bool>>,
+    /// Characters each glyph draws, from a PSF Unicode table.
+    unicode: Option<Vec<Vec<char>>>,
+}
+
+impl BitmapFont {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.glyphs.len()
+    }
+
+    /// Glyph mapping from the font's Unicode table, for `Console::set_code_page`.
+    /// Linux console fonts are rarely in CP437 order, so text needs this to pick
+    /// the right glyphs. Only the first 256 glyphs can be addressed by tiles.
+    pub fn code_page(&self) -> Option<CodePage> {
+        let unicode = self.unicode.as_ref()?;
+        let glyphs = unicode.iter().take(256).enumerate()
+            .flat_map(|(glyph, chars)| chars.iter().map(move |&c| (c, glyph as u8)));
+        Some(CodePage::from_glyphs(glyphs))
+    }
+
+    /// Parses a PC Screen Font, version 1 or 2, with its Unicode table if it has one.
+    pub fn from_psf(bytes: &[u8]) -> anyhow::Result<Self> {
+        if bytes.starts_with(&PSF1_MAGIC) {
+            Self::from_psf1(bytes)
+        } else if bytes.starts_with(&PSF2_MAGIC) {
+            Self::from_psf2(bytes)
+        } else {
+            bail!("not a PSF font")
+        }
+    }
+
+    fn from_psf1(bytes: &[u8]) -> anyhow::Result<Self> {
+        ensure!(bytes.len() >= 4, "PSF1 header is truncated");
+        let mode = bytes[2];
+        let height = bytes[3] as u32;
+        let count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+        let mut font = Self::from_packed_rows(&bytes[4..], count, 8, height)?;
+        if mode & PSF1_MODE_HAS_TAB != 0 {
+            let table = &bytes[4 + count * height as usize..];
+            font.unicode = Some(Self::parse_psf1_table(table, count)?);
+        }
+        Ok(font)
+    }
+
+    /// Reads `count` lists of UCS-2 characters, each ended by `PSF1_SEPARATOR`.
+    /// Multi-character sequences after `PSF1_START_SEQ` are skipped.
+    fn parse_psf1_table(table: &[u8], count: usize) -> anyhow::Result<Vec<Vec<char>>> {
+        let mut values = table.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+        (0..count).map(|glyph| {
+            let mut chars = Vec::new();
+            let mut in_sequence = false;
+            loop {
+                match values.next().ok_or_else(|| anyhow!("PSF1 Unicode table ends before glyph {glyph}"))? {
+                    PSF1_SEPARATOR => return Ok(chars),
+                    PSF1_START_SEQ => in_sequence = true,
+                    value if !in_sequence => chars.extend(char::from_u32(value as u32)),
+                    _ => {}
+                }
+            }
+        }).collect()
+    }
+
+    fn from_psf2(bytes: &[u8]) -> anyhow::Result<Self> {
+        ensure!(bytes.len() >= 32, "PSF2 header is truncated");
+        let field = |index: usize| u32::from_le_bytes(bytes[index * 4..index * 4 + 4].try_into().unwrap());
+        let header_size = field(2) as usize;
+        let count = field(4) as usize;
+        let bytes_per_glyph = field(5) as usize;
+        let height = field(6);
+        let width = field(7);
+        ensure!(header_size >= 32, "PSF2 header size {header_size} is smaller than the header");
+        ensure!((width.div_ceil(8) as usize).checked_mul(height as usize) == Some(bytes_per_glyph),
+            "PSF2 glyphs of {width}x{height} can't take {bytes_per_glyph} bytes");
+        let flags = field(3);
+        let data = bytes.get(header_size..).ok_or_else(|| anyhow!("PSF2 header size {header_size} is past the end of the file"))?;
+        let mut font = Self::from_packed_rows(data, count, width, height)?;
+        if flags & PSF2_HAS_UNICODE_TABLE != 0 {
+            // from_packed_rows has checked that the glyphs fit in `data`.
+            font.unicode = Some(Self::parse_psf2_table(&data[count * bytes_per_glyph..], count)?);
+        }
+        Ok(font)
+    }
+
+    /// Reads `count` UTF-8 strings, each ended by `PSF2_SEPARATOR`.
+    /// Multi-character sequences after `PSF2_START_SEQ` are skipped.
+    fn parse_psf2_table(table: &[u8], count: usize) -> anyhow::Result<Vec<Vec<char>>> {
+        let mut rest = table;
+        (0..count).map(|glyph| {
+            let end = rest.iter().position(|&byte| byte == PSF2_SEPARATOR)
+                .ok_or_else(|| anyhow!("PSF2 Unicode table ends before glyph {glyph}"))?;
+            let entry = &rest[..end];
+            rest = &rest[end + 1..];
+            let singles = entry.split(|&byte| byte == PSF2_START_SEQ).next().unwrap_or_default();
+            let singles = std::str::from_utf8(singles).with_context(|| format!("PSF2 Unicode table entry of glyph {glyph}"))?;
+            Ok(singles.chars().collect())
+        }).collect()
+    }
+
+    /// Reads `count` glyphs stored as rows of bits, most significant bit first,
+    /// each row padded to a whole byte.
+    fn from_packed_rows(data: &[u8], count: usize, width: u32, height: u32) -> anyhow::Result<Self> {
+        ensure!(width > 0 && height > 0, "glyphs of {width}x{height} are empty");
+        let row_bytes = width.div_ceil(8) as usize;
+        let glyph_bytes = row_bytes.checked_mul(height as usize)
+            .ok_or_else(|| anyhow!("glyphs of {width}x{height} are too large"))?;
+        let total_bytes = count.checked_mul(glyph_bytes)
+            .ok_or_else(|| anyhow!("{count} glyphs of {width}x{height} are too large"))?;
+        ensure!(data.len() >= total_bytes, "font data ends before glyph {}", data.len() / glyph_bytes);
+
+        let glyphs = data.chunks_exact(glyph_bytes).take(count).map(|glyph| {
+            glyph.chunks_exact(row_bytes)
+                .flat_map(|row| (0..width as usize).map(move |x| row[x / 8] & (0x80 >> (x % 8)) != 0))
+                .collect()
+        }).collect();
+        Ok(BitmapFont { width, height, glyphs, unicode: None })
+    }
+
+    /// Parses a Glyph Bitmap Distribution Format font, placing each glyph by its
+    /// `ENCODING`. Glyph boxes are aligned on the font bounding box's baseline.
+    pub fn from_bdf(source: &str) -> anyhow::Result<Self> {
+        let mut bounds = None;
+        let mut glyphs: Vec<Vec<bool>> = Vec::new();
+        let mut lines = source.lines().enumerate();
+
+        while let Some((number, line)) = lines.next() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("FONTBOUNDINGBOX") => {
+                    bounds = Some(Self::parse_numbers::<4>(words).with_context(|| format!("line {}", number + 1))?);
+                }
+                Some("STARTCHAR") => {
+                    let [width, height, x_offset, y_offset] = bounds.ok_or_else(|| anyhow!("line {}: STARTCHAR before FONTBOUNDINGBOX", number + 1))?;
+                    ensure!(width > 0 && height > 0, "font bounding box of {width}x{height} is empty");
+                    let cells = width.checked_mul(height)
+                        .ok_or_else(|| anyhow!("font bounding box of {width}x{height} is too large"))? as usize;
+                    let mut encoding = -1;
+                    let mut bbx = [width, height, x_offset, y_offset];
+                    let mut bitmap = None;
+
+                    for (number, line) in lines.by_ref() {
+                        let mut words = line.split_whitespace();
+                        match words.next() {
+                            Some("ENCODING") => encoding = Self::parse_numbers::<1>(words).with_context(|| format!("line {}", number + 1))?[0],
+                            Some("BBX") => bbx = Self::parse_numbers::<4>(words).with_context(|| format!("line {}", number + 1))?,
+                            Some("BITMAP") => bitmap = Some(Vec::new()),
+                            Some("ENDCHAR") => break,
+                            Some(hex) if bitmap.is_some() => {
+                                ensure!(hex.len() <= 16, "line {}: bitmap row {hex:?} is wider than 64 bits", number + 1);
+                                let row = u64::from_str_radix(hex, 16).with_context(|| format!("line {}: bad bitmap row {hex:?}", number + 1))?;
+                                bitmap.as_mut().unwrap().push((row, hex.len() as u32 * 4));
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    let Ok(index) = usize::try_from(encoding) else { continue };
+                    if index >= BDF_MAX_GLYPHS {
+                        continue;
+                    }
+                    if glyphs.len() <= index {
+                        glyphs.resize(index + 1, vec![false; cells]);
+                    }
+
+                    // Cell position of the glyph box's top left corner, in i64 so
+                    // offsets read from the file can't overflow.
+                    let [width, height] = [width as i64, height as i64];
+                    let left = bbx[2] as i64 - x_offset as i64;
+                    let top = (height + y_offset as i64) - (bbx[1] as i64 + bbx[3] as i64);
+                    for (row, (bits, bit_count)) in bitmap.unwrap_or_default().into_iter().enumerate() {
+                        for column in 0..(bbx[0] as i64).min(bit_count as i64) {
+                            let (x, y) = (left + column, top + row as i64);
+                            if bits & (1 << (bit_count as i64 - 1 - column)) != 0 && (0..width).contains(&x) && (0..height).contains(&y) {
+                                glyphs[index][(y * width + x) as usize] = true;
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let [width, height, ..] = bounds.ok_or_else(|| anyhow!("no FONTBOUNDINGBOX"))?;
+        ensure!(!glyphs.is_empty(), "no glyphs with encodings below {BDF_MAX_GLYPHS}");
+        Ok(BitmapFont { width: width as u32, height: height as u32, glyphs, unicode: None })
+    }
+
+    fn parse_numbers<'a, const N: usize>(mut words: impl Iterator<Item = &'a str>) -> anyhow::Result<[i32; N]> {
+        let mut numbers = [0; N];
+        for number in &mut numbers {
+            let word = words.next().ok_or_else(|| anyhow!("expected {N} numbers"))?;
+            *number = word.parse().with_context(|| format!("bad number {word:?}"))?;
+        }
+        Ok(numbers)
+    }
+
+    /// Rasterises the glyphs into an atlas, 16 per row: white where a glyph's bits
+    /// are set, transparent elsewhere.
+    pub fn to_atlas(&self) -> (RgbaImage, AtlasLayout) {
+        let layout = AtlasLayout {
+            glyph_width: self.width,
+            glyph_height: self.height,
+            columns: ATLAS_COLUMNS,
+            glyph_count: self.glyphs.len() as u32,
+            padding: 0,
+        };
+        let (width, height) = layout.size();
+        let mut image = RgbaImage::new(width, height);
+        for (index, glyph) in self.glyphs.iter().enumerate() {
+            let origin_x = index as u32 % layout.columns * self.width;
+            let origin_y = index as u32 / layout.columns * self.height;
+            for (pixel, _) in glyph.iter().enumerate().filter(|(_, &set)| set) {
+                let x = origin_x + pixel as u32 % self.width;
+                let y = origin_y + pixel as u32 / self.width;
+                image.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+            }
+        }
+        (image, layout)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(font: &BitmapFont, glyph: usize, x: u32, y: u32) -> bool {
+        font.glyphs[glyph][(y * font.width + x) as usize]
+    }
+
+    fn psf1(glyph_count: usize) -> Vec<u8> {
+        let mut bytes = vec![PSF1_MAGIC[0], PSF1_MAGIC[1], 0, 2];
+        bytes.resize(4 + glyph_count * 2, 0);
+        bytes[4 + 2] = 0x80;
+        bytes[4 + 3] = 0x01;
+        bytes
+    }
+
+    fn psf2(width: u32, height: u32, bytes_per_glyph: u32) -> Vec<u8> {
+        let header = [32, 0, 2, bytes_per_glyph, height, width];
+        let mut bytes = PSF2_MAGIC.to_vec();
+        bytes.extend([0u32].iter().chain(&header).flat_map(|field| field.to_le_bytes()));
+        bytes.resize(32 + 2 * bytes_per_glyph as usize, 0);
+        bytes
+    }
+
+    #[test]
+    fn parses_psf1() {
+        let font = BitmapFont::from_psf(&psf1(256)).unwrap();
+        assert_eq!((font.width(), font.height(), font.glyph_count()), (8, 2, 256));
+        assert!(pixel(&font, 1, 0, 0));
+        assert!(pixel(&font, 1, 7, 1));
+        assert!(!pixel(&font, 1, 1, 0));
+        assert!(!pixel(&font, 0, 0, 0));
+    }
+
+    #[test]
+    fn parses_psf2_with_rows_wider_than_a_byte() {
+        let mut bytes = psf2(10, 2, 4);
+        // Second glyph: bit 9 of the first row, bit 0 of the second.
+        bytes[32 + 4 + 1] = 0x40;
+        bytes[32 + 4 + 2] = 0x80;
+        let font = BitmapFont::from_psf(&bytes).unwrap();
+        assert_eq!((font.width(), font.height(), font.glyph_count()), (10, 2, 2));
+        assert!(pixel(&font, 1, 9, 0));
+        assert!(pixel(&font, 1, 0, 1));
+        assert!(!pixel(&font, 1, 8, 0));
+    }
+
+    #[test]
+    fn rejects_bad_psf() {
+        assert!(BitmapFont::from_psf(b"not a font").is_err());
+        assert!(BitmapFont::from_psf(&PSF1_MAGIC).is_err());
+        let mut truncated = psf1(256);
+        truncated.pop();
+        assert!(BitmapFont::from_psf(&truncated).is_err());
+        assert!(BitmapFont::from_psf(&psf2(10, 2, 3)).is_err());
+        assert!(BitmapFont::from_psf(&psf2(10, 2, 4)[..39]).is_err());
+        assert!(BitmapFont::from_psf(&PSF2_MAGIC).is_err());
+    }
+
+    #[test]
+    fn rejects_psf2_headers_with_impossible_sizes() {
+        let mut small_header = psf2(8, 2, 2);
+        small_header[8..12].copy_from_slice(&16u32.to_le_bytes());
+        assert!(BitmapFont::from_psf(&small_header).is_err());
+
+        let huge_glyphs = psf2(u32::MAX, u32::MAX, 0);
+        assert!(BitmapFont::from_psf(&huge_glyphs).is_err());
+
+        let mut many_glyphs = psf2(8, 2, 2);
+        many_glyphs[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(BitmapFont::from_psf(&many_glyphs).is_err());
+    }
+
+    const BDF: &str = "STARTFONT 2.1
+FONTBOUNDINGBOX 4 4 0 -1
+CHARS 2
+STARTCHAR A
+ENCODING 65
+BBX 2 2 1 0
+BITMAP
+C0
+40
+ENDCHAR
+STARTCHAR too high
+ENCODING 9731
+BBX 4 4 0 -1
+BITMAP
+F0
+F0
+F0
+F0
+ENDCHAR
+ENDFONT
+";
+
+    #[test]
+    fn parses_bdf_glyphs_on_the_baseline() {
+        let font = BitmapFont::from_bdf(BDF).unwrap();
+        assert_eq!((font.width(), font.height(), font.glyph_count()), (4, 4, 66));
+        let set: Vec<_> = (0..16).filter(|&i| pixel(&font, 65, i % 4, i / 4)).map(|i| (i % 4, i / 4)).collect();
+        assert_eq!(set, vec![(1, 1), (2, 1), (2, 2)]);
+        assert!(font.glyphs[..65].iter().all(|glyph| glyph.iter().all(|&set| !set)));
+    }
+
+    #[test]
+    fn rejects_bad_bdf() {
+        assert!(BitmapFont::from_bdf("STARTFONT 2.1\nENDFONT\n").is_err());
+        assert!(BitmapFont::from_bdf(&BDF.replace("FONTBOUNDINGBOX 4 4 0 -1\n", "")).is_err());
+        assert!(BitmapFont::from_bdf(&BDF.replace("BBX 2 2 1 0", "BBX 2 x 1 0")).is_err());
+        assert!(BitmapFont::from_bdf(&BDF.replace("C0", "ZZ")).is_err());
+        assert!(BitmapFont::from_bdf(&BDF.replace("ENCODING 65", "ENCODING 300")).is_err());
+        assert!(BitmapFont::from_bdf(&BDF.replace("FONTBOUNDINGBOX 4 4 0 -1", "FONTBOUNDINGBOX 65536 65536 0 -1")).is_err());
+        assert!(BitmapFont::from_bdf(&BDF.replace("C0", "00000000000000000C0")).is_err());
+    }
+
+    #[test]
+    fn places_bdf_glyphs_with_extreme_offsets_outside_the_cell() {
+        let font = BitmapFont::from_bdf(&BDF.replace("BBX 2 2 1 0", "BBX 2 2 2147483647 -2147483648")).unwrap();
+        assert!(font.glyphs[65].iter().all(|&set| !set));
+    }
+
+    #[test]
+    fn rasterises_glyphs_into_an_atlas() {
+        let font = BitmapFont::from_psf(&psf1(256)).unwrap();
+        let (image, layout) = font.to_atlas();
+        assert_eq!(layout, AtlasLayout { glyph_width: 8, glyph_height: 2, columns: 16, glyph_count: 256, padding: 0 });
+        assert_eq!(image.dimensions(), (128, 32));
+        assert_eq!(image.get_pixel(8, 0), &Rgba([255, 255, 255, 255]));
+        assert_eq!(image.get_pixel(15, 1), &Rgba([255, 255, 255, 255]));
+        assert_eq!(image.get_pixel(9, 0)[3], 0);
+    }
+
+    fn filled(width: u32, height: u32, value: u8) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, Rgba([value, value, value, 255]))
+    }
+
+    #[test]
+    fn packs_atlases_below_each_other() {
+        let grid = |columns| AtlasLayout { glyph_width: 8, glyph_height: 8, columns, glyph_count: 4, padding: 0 };
+        let mut atlases = AtlasSet::default();
+        atlases.set(0, filled(16, 16, 1), grid(2), 64).unwrap();
+        atlases.set(1, filled(32, 8, 2), grid(4), 64).unwrap();
+        assert_eq!(atlases.size(), [32, 24]);
+
+        let packed = atlases.pack();
+        assert_eq!(packed.get_pixel(0, 15)[0], 1);
+        assert_eq!(packed.get_pixel(31, 16)[0], 2);
+        assert_eq!(packed.get_pixel(31, 0)[3], 0);
+
+        assert_eq!(atlases.uv(0, 3), [0.25, 0.5, 8.0 / 24.0, 16.0 / 24.0]);
+        assert_eq!(atlases.uv(1, 1), [0.25, 0.5, 16.0 / 24.0, 1.0]);
+        // Unknown atlases fall back to atlas 0.
+        assert_eq!(atlases.uv(5, 3), atlases.uv(0, 3));
+    }
+
+    #[test]
+    fn rejects_atlases_past_the_texture_limit() {
+        let layout = AtlasLayout::grid_16x16(32, 32);
+        let mut atlases = AtlasSet::default();
+        atlases.set(0, filled(32, 32, 1), layout, 48).unwrap();
+        assert!(atlases.set(1, filled(32, 32, 2), layout, 48).is_err());
+        assert_eq!(atlases.len(), 1);
+        // Replacing an atlas only counts its new size.
+        atlases.set(0, filled(48, 48, 2), AtlasLayout::grid_16x16(48, 48), 48).unwrap();
+    }
+
+    #[test]
+    fn reads_psf1_unicode_tables() {
+        let mut bytes = psf1(256);
+        bytes[2] = PSF1_MODE_HAS_TAB;
+        let mut table = vec![0x41, PSF1_SEPARATOR, 0x263a, PSF1_START_SEQ, 0x42, 0x301, PSF1_SEPARATOR];
+        table.resize(256 + 5, PSF1_SEPARATOR);
+        bytes.extend(table.iter().flat_map(|value| value.to_le_bytes()));
+
+        let code_page = BitmapFont::from_psf(&bytes).unwrap().code_page().unwrap();
+        assert_eq!(code_page.glyph('A'), Some(0));
+        assert_eq!(code_page.glyph('☺'), Some(1));
+        assert_eq!(code_page.glyph('B'), None);
+
+        bytes.truncate(bytes.len() - 2);
+        assert!(BitmapFont::from_psf(&bytes).is_err());
+    }
+
+    #[test]
+    fn reads_psf2_unicode_tables() {
+        let mut bytes = psf2(10, 2, 4);
+        bytes[12] = PSF2_HAS_UNICODE_TABLE as u8;
+        bytes.extend("é╔".bytes());
+        bytes.push(PSF2_SEPARATOR);
+        bytes.extend("╗".bytes());
+        bytes.push(PSF2_START_SEQ);
+        bytes.extend("e\u{301}".bytes());
+        bytes.push(PSF2_SEPARATOR);
+
+        let code_page = BitmapFont::from_psf(&bytes).unwrap().code_page().unwrap();
+        assert_eq!(code_page.glyph('é'), Some(0));
+        assert_eq!(code_page.glyph('╔'), Some(0));
+        assert_eq!(code_page.glyph('╗'), Some(1));
+        assert_eq!(code_page.glyph('e'), None);
+
+        bytes.pop();
+        assert!(BitmapFont::from_psf(&bytes).is_err());
+        assert!(BitmapFont::from_psf(&psf2(10, 2, 4)).unwrap().code_page().is_none());
+    }
+}
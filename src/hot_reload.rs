@@ -0,0 +1,70 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use instant::Instant;
+
+
+/// How often `ShaderWatcher::changed_files` looks at the disk.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Watches WGSL files in the checkout's `res/shader/` by polling their modification times.
+///
+/// Only used on native builds; the build script's copy in `OUT_DIR` is never edited by hand.
+pub(crate) struct ShaderWatcher {
+    files: Vec<WatchedFile>,
+    last_poll: Instant,
+}
+
+struct WatchedFile {
+    name: String,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl ShaderWatcher {
+    pub fn new<'a>(names: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut files: Vec<WatchedFile> = Vec::new();
+        for name in names {
+            if files.iter().any(|file| file.name == name) {
+                continue;
+            }
+            let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("res").join("shader").join(name);
+            let modified = Self::modified(&path);
+            files.push(WatchedFile { name: name.to_string(), path, modified });
+        }
+        ShaderWatcher { files, last_poll: Instant::now() }
+    }
+
+    /// Names and new contents of the files changed since the last call.
+    ///
+    /// Files that can't be read, e.g. halfway through an editor's save, are retried on the next poll.
+    pub fn changed_files(&mut self) -> Vec<(String, String)> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+
+        let mut changed = Vec::new();
+        for file in &mut self.files {
+            let modified = Self::modified(&file.path);
+            if modified.is_none() || modified == file.modified {
+                continue;
+            }
+            if let Ok(source) = std::fs::read_to_string(&file.path) {
+                file.modified = modified;
+                changed.push((file.name.clone(), source));
+            }
+        }
+        changed
+    }
+
+    /// Reports every file but `name` again on the next poll, e.g. after declarations they share changed.
+    pub fn mark_others_changed(&mut self, name: &str) {
+        for file in self.files.iter_mut().filter(|file| file.name != name) {
+            file.modified = None;
+        }
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+    }
+}
@@ -5,33 +5,48 @@ use wasm_bindgen::prelude::*;
 
 use std::sync::{Arc, Mutex};
 use winit::dpi::*;
-use winit::event::{Event, WindowEvent};
+use winit::event::{ElementState, Event, KeyEvent, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
+use winit::keyboard::{KeyCode, PhysicalKey};
 use winit::window::WindowBuilder;
-use crate::config::GameConfig;
-use crate::renderer::Renderer;
 
 mod renderer;
 mod config;
 mod buffer;
 mod resources;
+mod console;
+mod crt;
+mod font;
+mod codepage;
+#[cfg(not(target_arch = "wasm32"))]
+mod hot_reload;
+
+pub use crate::codepage::{BuiltinCodePage, CodePage};
+pub use crate::config::{CodePageConfig, ConfigError, GameConfig, PostPass, Tile, ATTR_BLINK, ATTR_BOLD, ATTR_DIM, ATTR_REVERSE, ATTR_TRANSPARENT, ATTR_UNDERLINE, POST_UNIFORM_COUNT};
+pub use crate::console::Console;
+pub use crate::crt::{CrtParams, CrtPreset, PhosphorMask};
+pub use crate::font::{AtlasLayout, BitmapFont};
+pub use crate::renderer::{RenderError, Renderer};
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub async fn start() {
-
-    let config = GameConfig::new().await;
-
-
-    let width = config.options.screen_size[0];
-    let height = config.options.screen_size[1];
-
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
             std::panic::set_hook(Box::new(console_error_panic_hook::hook));
             console_log::init_with_level(log::Level::Warn).expect("Couldn't initialize logger");
+        } else {
+            // Shader reload errors and screenshots are reported through `log`.
+            env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn,crt_like_js_renderer=info")).init();
         }
     }
 
+    let config = GameConfig::new().await
+        .unwrap_or_else(|err| panic!("Invalid game config: {err}"));
+
+
+    let width = config.options.screen_size[0];
+    let height = config.options.screen_size[1];
+    let screenshot_scale = config.options.screenshot_scale;
 
     let event_loop = EventLoop::new().unwrap();
 
@@ -75,8 +90,7 @@ pub async fn start() {
 
     {
         let mut renderer = renderer.lock().unwrap();
-        renderer.set_texture(&res);
-        renderer.init_instances();
+        renderer.set_texture(&res).unwrap_or_else(|err| panic!("Failed to load the glyph atlas: {err}"));
     }
 
 
@@ -90,6 +104,49 @@ pub async fn start() {
                 println!("The close button was pressed; stopping");
                 elwt.exit();
             },
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput {
+                    event: KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F12),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                    ..
+                },
+                ..
+            } => {
+                save_screenshot(&mut renderer.lock().unwrap(), screenshot_scale);
+            },
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput {
+                    event: KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F9),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                    ..
+                },
+                ..
+            } => {
+                let mut renderer = renderer.lock().unwrap();
+                let preset = renderer.preset().map_or(CrtPreset::ALL[0], CrtPreset::next);
+                renderer.set_preset(preset);
+                log::info!("CRT preset: {preset:?}");
+            },
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                ..
+            } => {
+                renderer.lock().unwrap().resize(size);
+            },
+            Event::WindowEvent {
+                event: WindowEvent::ScaleFactorChanged { .. },
+                ..
+            } => {
+                renderer.lock().unwrap().resize(window.inner_size());
+            },
             Event::AboutToWait => {
                 // Application update code.
 
@@ -98,7 +155,11 @@ pub async fn start() {
                 // You only need to call this if you've determined that you need to redraw, in
                 // applications which do not always need to. Applications that redraw continuously
                 // can just render here instead.
-                renderer.lock().unwrap().render().expect("TODO: panic message");
+                if let Err(err) = renderer.lock().unwrap().render() {
+                    log::error!("Stopping: {err}");
+                    elwt.exit();
+                    return;
+                }
                 window.request_redraw();
             },
             Event::WindowEvent {
@@ -116,4 +177,23 @@ pub async fn start() {
     }).expect("TODO: panic message");
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+fn save_screenshot(renderer: &mut Renderer, scale: f32) {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    // Never overwrite an earlier capture, even from the same millisecond.
+    let path = (0..)
+        .map(|n| if n == 0 { format!("screenshot-{timestamp}.png") } else { format!("screenshot-{timestamp}-{n}.png") })
+        .find(|path| !std::path::Path::new(path).exists())
+        .unwrap();
+    match renderer.save_screenshot(&path, scale) {
+        Ok(()) => log::info!("Saved screenshot to {path}"),
+        Err(err) => log::error!("Screenshot failed: {err}"),
+    }
+}
 
+// Browsers have no file system to write the PNG to.
+#[cfg(target_arch = "wasm32")]
+fn save_screenshot(_renderer: &mut Renderer, _scale: f32) {}
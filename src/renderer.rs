@@ -1,51 +1,270 @@
+use std::fmt;
 use std::iter;
+use std::path::Path;
 use instant::Instant;
 use std::sync::Arc;
-use image::GenericImageView;
+use anyhow::{anyhow, ensure};
+use image::{ImageFormat, RgbaImage};
 use winit::window::Window;
 use wgpu::*;
 use wgpu::util::DeviceExt;
 use crate::buffer::*;
-use winit::dpi::PhysicalSize;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
 use crate::config::*;
+use crate::console::Console;
+use crate::font::{AtlasLayout, AtlasSet, BitmapFont};
+use crate::crt::{CrtParams, CrtPreset};
+use crate::resources::load_string;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::hot_reload::ShaderWatcher;
 
 
 
+/// Where the post-processed frame ends up.
+enum RenderTarget {
+    Surface(Arc<Surface<'static>>, SurfaceConfiguration),
+    /// Headless rendering into a texture that can be read back with `render_to_image`.
+    Offscreen(Texture),
+}
+
+/// Errors from `Renderer::render` that can't be recovered by skipping or
+/// reconfiguring; lost and outdated surfaces are handled internally.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenderError {
+    /// There is no memory left to allocate a new frame.
+    OutOfMemory,
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::OutOfMemory => write!(f, "out of memory while acquiring the next frame"),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+/// Region of an output texture the grid is drawn into, in pixels.
+#[derive(Clone, Copy, Debug)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    /// Fits `base` into `target` keeping its aspect ratio, centred with black bars.
+    /// With `integer_scaling` the scale is rounded down to a whole number when the target allows it.
+    pub fn letterbox(target: PhysicalSize<u32>, base: PhysicalSize<u32>, integer_scaling: bool) -> Self {
+        let scale_x = target.width as f32 / base.width as f32;
+        let scale_y = target.height as f32 / base.height as f32;
+        let mut scale = scale_x.min(scale_y);
+        if integer_scaling && scale >= 1.0 {
+            scale = scale.floor();
+        }
+
+        let width = (base.width as f32 * scale).round();
+        let height = (base.height as f32 * scale).round();
+        Viewport {
+            x: ((target.width as f32 - width) / 2.0).floor(),
+            y: ((target.height as f32 - height) / 2.0).floor(),
+            width,
+            height,
+        }
+    }
+}
+
+/// Number of bloom textures, each half the size of the one before, starting at half resolution.
+const BLOOM_LEVELS: usize = 2;
+
+/// Intermediate textures sized to the letterboxed viewport, rebuilt on resize.
+struct FrameTargets {
+    /// Tile pass output and the bind group the CRT pass samples it through.
+    main_view: TextureView,
+    post_process_bind_group: BindGroup,
+    /// Tile pass output sampled with the bloom sampler, so the threshold pass averages it down.
+    bloom_source_bind_group: BindGroup,
+    /// Bloom downsample chain; the blur runs on the last, smallest level.
+    bloom_views: Vec<TextureView>,
+    bloom_bind_groups: Vec<BindGroup>,
+    /// Holds the horizontally blurred last bloom level before the vertical blur.
+    bloom_blur_view: TextureView,
+    bloom_blur_bind_group: BindGroup,
+    /// Ping-pong outputs of each post pass: one is written this frame while the
+    /// other holds the pass's own previous output, read back for phosphor persistence.
+    pass_views: Vec<[TextureView; 2]>,
+    pass_bind_groups: Vec<[BindGroup; 2]>,
+}
+
+impl FrameTargets {
+    fn new(device: &Device, format: TextureFormat, viewport: &Viewport, layout: &BindGroupLayout, sampler: &Sampler, bloom_sampler: &Sampler, pass_scales: &[f32]) -> Self {
+        let size = [(viewport.width.round() as u32).max(1), (viewport.height.round() as u32).max(1)];
+        let (main_view, post_process_bind_group) = Self::create_sampled_target(device, "Main render texture", format, size, layout, sampler);
+        let bloom_source_bind_group = Self::create_bind_group(device, "Bloom source", &main_view, layout, bloom_sampler);
+        let bloom_size = |level: usize| size.map(|extent| (extent >> (level + 1)).max(1));
+        let (bloom_views, bloom_bind_groups) = (0..BLOOM_LEVELS)
+            .map(|level| Self::create_sampled_target(device, "Bloom texture", format, bloom_size(level), layout, bloom_sampler))
+            .unzip();
+        let (bloom_blur_view, bloom_blur_bind_group) = Self::create_sampled_target(device, "Bloom blur texture", format, bloom_size(BLOOM_LEVELS - 1), layout, bloom_sampler);
+        let (pass_views, pass_bind_groups) = pass_scales.iter()
+            .map(|scale| {
+                let pass_size = size.map(|extent| ((extent as f32 * scale).round() as u32).max(1));
+                let (view_a, bind_group_a) = Self::create_sampled_target(device, "Post pass texture A", format, pass_size, layout, sampler);
+                let (view_b, bind_group_b) = Self::create_sampled_target(device, "Post pass texture B", format, pass_size, layout, sampler);
+                ([view_a, view_b], [bind_group_a, bind_group_b])
+            })
+            .unzip();
+        FrameTargets {
+            main_view,
+            post_process_bind_group,
+            bloom_source_bind_group,
+            bloom_views,
+            bloom_bind_groups,
+            bloom_blur_view,
+            bloom_blur_bind_group,
+            pass_views,
+            pass_bind_groups,
+        }
+    }
+
+    /// A render target plus the bind group later passes sample it through.
+    fn create_sampled_target(device: &Device, label: &str, format: TextureFormat, size: [u32; 2], layout: &BindGroupLayout, sampler: &Sampler) -> (TextureView, BindGroup) {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size : Extent3d{
+                width : size[0],
+                height : size[1],
+                depth_or_array_layers:1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let bind_group = Self::create_bind_group(device, label, &view, layout, sampler);
+        (view, bind_group)
+    }
+
+    fn create_bind_group(device: &Device, label: &str, view: &TextureView, layout: &BindGroupLayout, sampler: &Sampler) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(sampler),
+                }
+            ],
+            label: Some(label),
+        })
+    }
+}
+
+/// Shaders of the built-in passes, loaded from `res/shader/` like the post passes.
+const TILE_SHADER: &str = "colour_tile.wgsl";
+const BLOOM_SHADER: &str = "bloom.wgsl";
+const BLIT_SHADER: &str = "blit.wgsl";
+/// Shared declarations, such as `CrtParams`, appended to every shader so that
+/// naga's line numbers still match the shader's own file.
+const SHARED_SHADER: &str = "crt_params.wgsl";
+
+/// WGSL sources read at startup, before any pipeline is built.
+struct ShaderSources {
+    shared: String,
+    tile: String,
+    bloom: String,
+    blit: String,
+    /// One per post pass, in chain order.
+    post: Vec<String>,
+}
+
+/// A compiled `PostPass`.
+struct PostStage {
+    shader: String,
+    scale: f32,
+    pipeline: RenderPipeline,
+    /// group(1): the shared time, screen and CRT uniforms plus this pass's own uniforms.
+    bind_group: BindGroup,
+}
+
 pub struct Renderer {
     pub device: Device,
-    surface: Arc<Surface<'static>>,
+    target: RenderTarget,
+    format: TextureFormat,
+    size: PhysicalSize<u32>,
+    /// Logical screen resolution the grid is letterboxed to.
+    base_size: PhysicalSize<u32>,
+    integer_scaling: bool,
 
     pub queue: Queue,
-    screen_buffer : [Tile; SCREEN_COLS * SCREEN_ROWS],
+    screen_buffer : Console,
 
 
-    main_view : TextureView,
+    targets : FrameTargets,
+    /// Which texture of each post pass's pair is written this frame.
+    history_index : usize,
 
     camera_bind_group: BindGroup,
 
     texture_bind_group_layout: BindGroupLayout,
     time_bind_group:BindGroup,
     time_buffer:Buffer,
+    crt_buffer:Buffer,
+    crt_params: CrtParams,
+    preset: Option<CrtPreset>,
 
     diffuse_render_pipeline: RenderPipeline,
-    post_render_pipeline: RenderPipeline,
+    diffuse_render_pipeline_layout: PipelineLayout,
+    post_render_pipeline_layout: PipelineLayout,
+    bloom_pipeline_layout: PipelineLayout,
+    blit_pipeline_layout: PipelineLayout,
+    post_stages: Vec<PostStage>,
+    blit_pipeline: RenderPipeline,
+    bloom_threshold_pipeline: RenderPipeline,
+    bloom_blur_pipelines: [RenderPipeline; 2],
 
 
 
     sampler: Sampler,
+    bloom_sampler: Sampler,
     bind_group: Option<Arc<BindGroup>>,
-    post_process_bind_group : BindGroup,
+    /// Glyph atlases packed into the texture of `bind_group`.
+    atlases: AtlasSet,
+    /// Layout from the config's `[atlas]` table, used by `set_texture`.
+    texture_layout: Option<AtlasLayout>,
 
     mesh: Mesh,
     screen_mesh: Mesh,
 
     init_time : Instant,
+    /// Frames prepared so far, uploaded next to the time for interlacing.
+    frame_index : u32,
+
+    /// Returned in place of the next acquired frame, see `inject_surface_error`.
+    #[cfg(test)]
+    injected_error: Option<SurfaceError>,
 
+    /// Contents of `SHARED_SHADER`, appended to every shader source.
+    shared_shader: String,
+    #[cfg(not(target_arch = "wasm32"))]
+    shader_watcher: ShaderWatcher,
 }
 
 impl Renderer {
     pub async fn new(window: Arc<Window>, game_config: &GameConfig) -> Self {
-        let size = PhysicalSize::new(game_config.options.screen_size[0] * 2, game_config.options.screen_size[1] * 2);
+        // The canvas may not be laid out yet on the web, so fall back to the configured size.
+        let size = match window.inner_size() {
+            size if size.width > 0 && size.height > 0 => size,
+            _ => PhysicalSize::new(game_config.options.screen_size[0] * 2, game_config.options.screen_size[1] * 2),
+        };
         let instance = Instance::new(&InstanceDescriptor::default());
         let surface = Arc::new(instance.create_surface(window).unwrap());
         let adapter = instance
@@ -56,25 +275,9 @@ impl Renderer {
             })
             .await
             .unwrap();
-        let (device, queue) = adapter
-            .request_device(
-                &DeviceDescriptor {
-                    label: None,
-                    required_features: Features::empty(),
-                    // WebGL doesn't support all of wgpu`s features, so if
-                    // we're building for the web we'll have to disable some.
-                    required_limits: if cfg!(target_arch = "wasm32") {
-                        Limits::downlevel_webgl2_defaults()
-                    } else {
-                        Limits::default()
-                    },
-                    experimental_features: wgpu::ExperimentalFeatures::disabled(),
-                    memory_hints: wgpu::MemoryHints::default(),
-                    trace: wgpu::Trace::Off,
-                },
-            )
-            .await
-            .unwrap();
+        let (device, queue) = Self::request_device(&adapter).await.unwrap();
+        let shaders = Self::load_shaders(game_config).await
+            .unwrap_or_else(|err| panic!("Failed to load shaders: {err}"));
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps.formats.iter()
             .copied()
@@ -93,6 +296,71 @@ impl Renderer {
         };
         surface.configure(&device, &config);
 
+        Self::with_target(device, queue, RenderTarget::Surface(surface, config), surface_format, size, game_config, shaders).await
+            .unwrap_or_else(|err| panic!("Failed to build pipelines: {err}"))
+    }
+
+    /// Creates a renderer without a window that draws into an offscreen texture.
+    ///
+    /// Pass `force_fallback_adapter` to run on a software adapter, e.g. on CI machines without a GPU.
+    pub async fn new_headless(game_config: &GameConfig, force_fallback_adapter: bool) -> anyhow::Result<Self> {
+        let size = PhysicalSize::new(game_config.options.screen_size[0] * 2, game_config.options.screen_size[1] * 2);
+        let instance = Instance::new(&InstanceDescriptor::default());
+        let adapter = instance
+            .request_adapter(&RequestAdapterOptions {
+                power_preference: PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter,
+            })
+            .await?;
+        let (device, queue) = Self::request_device(&adapter).await?;
+        let shaders = Self::load_shaders(game_config).await?;
+
+        let format = TextureFormat::Rgba8UnormSrgb;
+        let texture = Self::create_output_texture(&device, format, size);
+
+        Self::with_target(device, queue, RenderTarget::Offscreen(texture), format, size, game_config, shaders).await
+    }
+
+    async fn load_shaders(game_config: &GameConfig) -> anyhow::Result<ShaderSources> {
+        let shared = Self::load_shader(SHARED_SHADER).await?;
+        let tile = Self::load_shader(TILE_SHADER).await?;
+        let bloom = Self::load_shader(BLOOM_SHADER).await?;
+        let blit = Self::load_shader(BLIT_SHADER).await?;
+        let mut post = Vec::with_capacity(game_config.post.len());
+        for pass in &game_config.post {
+            post.push(Self::load_shader(&pass.get_ref().shader).await?);
+        }
+        Ok(ShaderSources { shared, tile, bloom, blit, post })
+    }
+
+    async fn load_shader(file: &str) -> anyhow::Result<String> {
+        load_string(&format!("shader/{file}")).await
+            .map_err(|err| anyhow!("{file}: {err}"))
+    }
+
+    async fn request_device(adapter: &Adapter) -> Result<(Device, Queue), RequestDeviceError> {
+        adapter
+            .request_device(
+                &DeviceDescriptor {
+                    label: None,
+                    required_features: Features::empty(),
+                    // WebGL doesn't support all of wgpu`s features, so if
+                    // we're building for the web we'll have to disable some.
+                    required_limits: if cfg!(target_arch = "wasm32") {
+                        Limits::downlevel_webgl2_defaults()
+                    } else {
+                        Limits::default()
+                    },
+                    experimental_features: wgpu::ExperimentalFeatures::disabled(),
+                    memory_hints: wgpu::MemoryHints::default(),
+                    trace: wgpu::Trace::Off,
+                },
+            )
+            .await
+    }
+
+    async fn with_target(device: Device, queue: Queue, target: RenderTarget, surface_format: TextureFormat, size: PhysicalSize<u32>, game_config: &GameConfig, shaders: ShaderSources) -> anyhow::Result<Self> {
         // let viewport_data = [0., 0., size.width as f32, size.height as f32, 0., 1.];
 
 
@@ -165,14 +433,47 @@ impl Renderer {
                         min_binding_size: None,
                     },
                     count: None,
+                },
+                BindGroupLayoutEntry{
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry{
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 }
             ],
         });
+        let columns = game_config.options.columns;
+        let rows = game_config.options.rows;
         let time_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
                 label: Some("Time Buffer"),
                 contents: bytemuck::cast_slice(&[0.5, 0.0, 0.0, 0.0]),
                 usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
             });
+        let screen_uniform_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+                label: Some("Screen Buffer"),
+                contents: bytemuck::cast_slice(&[ScreenUniform::new(columns, rows)]),
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            });
+        let crt_params = game_config.crt_params();
+        let crt_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+                label: Some("Crt Params Buffer"),
+                contents: bytemuck::cast_slice(&[crt_params]),
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            });
         let time_bind_group = device.create_bind_group(&BindGroupDescriptor {
             label: Some("time_bind_group"),
             layout: &time_bind_group_layout,
@@ -180,121 +481,120 @@ impl Renderer {
                 BindGroupEntry {
                     binding: 0,
                     resource: time_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: screen_uniform_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: crt_buffer.as_entire_binding(),
                 }
             ],
         });
 
-        let shader = device.create_shader_module(include_wgsl!("../res/shader/colour_tile.wgsl"));
         let diffuse_render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
             bind_group_layouts: &[
                 &camera_bind_group_layout,
-                &texture_bind_group_layout
+                &texture_bind_group_layout,
+                &time_bind_group_layout
             ],
             push_constant_ranges: &[],
         });
-        let diffuse_render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("Base Render Pipeline"),
-            layout: Some(&diffuse_render_pipeline_layout),
-            vertex: VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                buffers: &vec![Vertex::desc(), InstanceTileRaw::desc()],
-                compilation_options: PipelineCompilationOptions::default(),
-            },
-            fragment: Some(FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(ColorTargetState {
-                    format: surface_format,
-                    blend: Some(BlendState::ALPHA_BLENDING),
-                    write_mask: ColorWrites::ALL,
-                })],
-                compilation_options: PipelineCompilationOptions::default(),
-            }),
-            primitive: PrimitiveState {
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: true,
-            },
-            multiview: None,
-            cache: None,
-        });
+        let diffuse_render_pipeline = Self::create_pipeline_checked(&device, &shaders.shared, TILE_SHADER, &shaders.tile, |module| {
+            Self::create_tile_pipeline(&device, module, &diffuse_render_pipeline_layout, surface_format)
+        }).await?;
 
         //endregion
 
         //region [ Post Render Path ]
-        let crt_shader = device.create_shader_module(include_wgsl!("../res/shader/post_crt.wgsl"));
+        // Post passes see the time, screen and CRT uniforms of the tile pass
+        // plus a block of their own at binding 3.
+        let post_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("post_bind_group_layout"),
+            entries: &[0, 1, 2, 3].map(|binding| BindGroupLayoutEntry{
+                binding,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }),
+        });
         let post_render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
             bind_group_layouts: &[
                 &texture_bind_group_layout,
-                &time_bind_group_layout
+                &post_bind_group_layout,
+                &texture_bind_group_layout,
+                &texture_bind_group_layout
             ],
             push_constant_ranges: &[],
         });
-        let post_render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("Post Render Pipeline"),
-            layout: Some(&post_render_pipeline_layout),
-            vertex: VertexState {
-                module: &crt_shader,
-                entry_point: Some("vs_main"),
-                buffers: &vec![Vertex::desc()],
-                compilation_options: PipelineCompilationOptions::default(),
-            },
-            fragment: Some(FragmentState {
-                module: &crt_shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(ColorTargetState {
-                    format: surface_format,
-                    blend: Some(BlendState::ALPHA_BLENDING),
-                    write_mask: ColorWrites::ALL,
-                })],
-                compilation_options: PipelineCompilationOptions::default(),
-            }),
-            primitive: PrimitiveState {
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: true,
-            },
-            multiview: None,
-            cache: None,
+        let mut post_stages = Vec::with_capacity(game_config.post.len());
+        for (pass, source) in game_config.post.iter().zip(&shaders.post) {
+            let pass = pass.get_ref();
+            let pipeline = Self::create_pipeline_checked(&device, &shaders.shared, &pass.shader, source, |module| {
+                Self::create_screen_pipeline(&device, &pass.shader, module, "fs_main", &post_render_pipeline_layout, surface_format)
+            }).await?;
+
+            let mut uniforms = [0.0f32; POST_UNIFORM_COUNT];
+            uniforms[..pass.uniforms.len()].copy_from_slice(&pass.uniforms);
+            let uniform_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+                label: Some("Post Pass Uniform Buffer"),
+                contents: bytemuck::cast_slice(&uniforms),
+                usage: BufferUsages::UNIFORM,
+            });
+            let bind_group = device.create_bind_group(&BindGroupDescriptor {
+                label: Some("post_bind_group"),
+                layout: &post_bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: time_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: screen_uniform_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: crt_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: uniform_buffer.as_entire_binding(),
+                    }
+                ],
+            });
+            post_stages.push(PostStage { shader: pass.shader.clone(), scale: pass.scale, pipeline, bind_group });
+        }
+
+        let bloom_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[
+                &texture_bind_group_layout,
+                &time_bind_group_layout
+            ],
+            push_constant_ranges: &[],
         });
-        let main_texture = device.create_texture(&TextureDescriptor {
-            label: Some("Main render texture"),
-            size : Extent3d{
-                width : size.width,
-                height : size.height,
-                depth_or_array_layers:1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: TextureDimension::D2,
-            format: surface_format,
-            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
-            view_formats: &[],
+        let (bloom_threshold_pipeline, bloom_blur_pipelines) = Self::create_pipeline_checked(&device, &shaders.shared, BLOOM_SHADER, &shaders.bloom, |module| {
+            Self::create_bloom_pipelines(&device, module, &bloom_pipeline_layout, surface_format)
+        }).await?;
+
+        let blit_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Blit Pipeline Layout"),
+            bind_group_layouts: &[
+                &texture_bind_group_layout
+            ],
+            push_constant_ranges: &[],
         });
-        let main_view = main_texture.create_view(&TextureViewDescriptor::default());
+        let blit_pipeline = Self::create_pipeline_checked(&device, &shaders.shared, BLIT_SHADER, &shaders.blit, |module| {
+            Self::create_screen_pipeline(&device, "Blit Pipeline", module, "fs_main", &blit_pipeline_layout, surface_format)
+        }).await?;
         let sampler = device.create_sampler(&SamplerDescriptor {
             address_mode_u: AddressMode::ClampToEdge,
             address_mode_v: AddressMode::ClampToEdge,
@@ -304,61 +604,82 @@ impl Renderer {
             mipmap_filter: FilterMode::Linear,
             ..Default::default()
         });
-        let post_process_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::TextureView(&main_view),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::Sampler(&sampler),
-                }
-            ],
-            label: Some("diffuse_bind_group"),
+        let bloom_sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
         });
+        let base_size = PhysicalSize::new(game_config.options.screen_size[0], game_config.options.screen_size[1]);
+        let integer_scaling = game_config.options.integer_scaling;
+        let viewport = Viewport::letterbox(size, base_size, integer_scaling);
+        let targets = FrameTargets::new(&device, surface_format, &viewport, &texture_bind_group_layout, &sampler, &bloom_sampler, &Self::pass_scales(&post_stages));
 
         //endregion
 
         let tile_size = [
-            2.0 / SCREEN_COLS as f32,
-            2.0 / SCREEN_ROWS as f32
+            2.0 / columns as f32,
+            2.0 / rows as f32
         ];
-        let mesh = Mesh::new(&device, &tile_size);
+        let layers = game_config.options.layers;
+        let mesh = Mesh::new(&device, &tile_size, (columns * rows * layers) as u32);
 
 
 
-        let screen_mesh = Mesh::new(&device, &[2.0,2.0]);
-        let mut screen_buffer = [ Tile::default(); SCREEN_ROWS * SCREEN_COLS];
-        let map = game_config.get_map();
-        for (index, tile) in map.iter().enumerate(){
-            screen_buffer[index].char = tile.char;
-            screen_buffer[index].color = tile.color;
-        }
+        let screen_mesh = Mesh::new(&device, &[2.0,2.0], 0);
+        let mut console = Console::with_layers(columns, rows, layers);
+        console.set_code_page(game_config.code_page());
+        console.load_rows(&game_config.get_map());
 
         let init_time = Instant::now();
+        #[cfg(not(target_arch = "wasm32"))]
+        let shader_watcher = ShaderWatcher::new([SHARED_SHADER, TILE_SHADER, BLOOM_SHADER, BLIT_SHADER].into_iter().chain(post_stages.iter().map(|stage| stage.shader.as_str())));
 
 
-        Self {
+        Ok(Self {
             device,
-            surface,
+            target,
+            format: surface_format,
+            size,
+            base_size,
+            integer_scaling,
             queue,
             mesh,
             screen_mesh,
             diffuse_render_pipeline,
             time_bind_group,
             time_buffer,
+            crt_buffer,
+            crt_params,
+            preset: game_config.options.preset,
             texture_bind_group_layout,
             bind_group: None,
+            atlases: AtlasSet::default(),
+            texture_layout: game_config.atlas.as_ref().map(|atlas| *atlas.get_ref()),
             sampler,
-            screen_buffer,
-            post_process_bind_group,
-            post_render_pipeline,
-            main_view,
+            bloom_sampler,
+            screen_buffer: console,
+            post_stages,
+            blit_pipeline,
+            bloom_threshold_pipeline,
+            bloom_blur_pipelines,
+            targets,
+            history_index: 0,
             init_time,
-            camera_bind_group
-        }
+            frame_index: 0,
+            #[cfg(test)]
+            injected_error: None,
+            camera_bind_group,
+            diffuse_render_pipeline_layout,
+            post_render_pipeline_layout,
+            bloom_pipeline_layout,
+            blit_pipeline_layout,
+            shared_shader: shaders.shared,
+            #[cfg(not(target_arch = "wasm32"))]
+            shader_watcher,
+        })
     }
 
 
@@ -383,11 +704,43 @@ impl Renderer {
         OPENGL_TO_WGPU_MATRIX * ortho * view
     }
 
-    pub fn set_texture(&mut self, bytes: &[u8]) {
-        // let img = image
-        let img = image::load_from_memory(bytes).unwrap();
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+    /// Uses an image as the text atlas, atlas 0, laid out as the config's `[atlas]`
+    /// table describes, or as 256 glyphs in a 16x16 grid like res/chr.png without one.
+    /// Fails if the image can't be decoded or is too small for the layout.
+    pub fn set_texture(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        let img = image::load_from_memory(bytes)?.to_rgba8();
+        let layout = self.texture_layout.unwrap_or_else(|| AtlasLayout::grid_16x16(img.width(), img.height()));
+        self.set_atlas(0, img, layout)
+    }
+
+    /// Rasterises a bitmap font and uses it as the text atlas, atlas 0.
+    pub fn set_font(&mut self, font: &BitmapFont) -> anyhow::Result<()> {
+        let (image, layout) = font.to_atlas();
+        self.set_atlas(0, image, layout)
+    }
+
+    /// Registers another glyph atlas, e.g. a tileset or icons, and returns the index
+    /// cells address it with through `Tile::atlas`. Atlas 0 holds the text glyphs.
+    pub fn add_atlas(&mut self, image: RgbaImage, layout: AtlasLayout) -> anyhow::Result<u8> {
+        let index = self.atlases.len().max(1);
+        ensure!(index <= u8::MAX as usize, "at most {} atlases are supported", u8::MAX as usize + 1);
+        self.set_atlas(index, image, layout)?;
+        Ok(index as u8)
+    }
+
+    /// Number of registered glyph atlases, including the text atlas.
+    pub fn atlas_count(&self) -> usize {
+        self.atlases.len()
+    }
+
+    /// Replaces or adds atlas `index` and re-uploads the packed atlas texture.
+    fn set_atlas(&mut self, index: usize, image: RgbaImage, layout: AtlasLayout) -> anyhow::Result<()> {
+        ensure!(index <= self.atlases.len(), "set the text atlas before adding atlas {index}");
+        layout.validate(image.width(), image.height())?;
+        self.atlases.set(index, image, layout, self.device.limits().max_texture_dimension_2d)?;
+
+        let rgba = self.atlases.pack();
+        let dimensions = rgba.dimensions();
         let size = Extent3d {
             width: dimensions.0,
             height: dimensions.1,
@@ -439,39 +792,486 @@ impl Renderer {
         });
 
         self.bind_group = Some(Arc::from(diffuse_bind_group));
+        // Every cell's UVs depend on the packing.
+        self.screen_buffer.mark_all_dirty();
+        Ok(())
+    }
+    /// Text console backing the tile grid. Changed cells are uploaded on the next `render`.
+    pub fn console(&mut self) -> &mut Console {
+        &mut self.screen_buffer
+    }
+
+    /// Re-uploads the instances of every cell written since the last call.
+    pub fn update_instances(&mut self){
+        let stride = std::mem::size_of::<InstanceTileRaw>() as BufferAddress;
+        let columns = self.screen_buffer.width();
+        let rows = self.screen_buffer.height();
+        for range in self.screen_buffer.take_dirty_ranges() {
+            let instances = self.screen_buffer.tiles()[range.clone()].iter().enumerate().map(|(offset, &tile)|{
+                let i = (range.start + offset) % (columns * rows);
+                TileRenderData{
+                    char : tile.char,
+                    atlas : tile.atlas,
+                    position: [i  % columns, i  / columns],
+                    color : tile.color,
+                    bg : tile.bg,
+                    attr : tile.attr
+                }.get_instance_matrix(columns, rows, &self.atlases)
+            }).collect::<Vec<_>>();
+
+            self.queue.write_buffer(&self.mesh.instance_buffer, range.start as BufferAddress * stride, bytemuck::cast_slice(&instances));
+        }
+    }
+    /// Reconfigures the output for a new window size and re-letterboxes the grid.
+    /// Zero-sized requests, e.g. from a minimised window, are ignored.
+    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        if new_size.width == 0 || new_size.height == 0 {
+            return;
+        }
+        self.size = new_size;
+
+        match &mut self.target {
+            RenderTarget::Surface(surface, config) => {
+                config.width = new_size.width;
+                config.height = new_size.height;
+                surface.configure(&self.device, config);
+            }
+            RenderTarget::Offscreen(texture) => {
+                *texture = Self::create_output_texture(&self.device, self.format, new_size);
+            }
+        }
+
+        let viewport = Viewport::letterbox(self.size, self.base_size, self.integer_scaling);
+        self.targets = FrameTargets::new(&self.device, self.format, &viewport, &self.texture_bind_group_layout, &self.sampler, &self.bloom_sampler, &Self::pass_scales(&self.post_stages));
+    }
+
+    pub fn crt_params(&self) -> CrtParams {
+        self.crt_params
+    }
+
+    /// Replaces the CRT post-process parameters; takes effect on the next frame.
+    pub fn set_crt_params(&mut self, params: CrtParams) {
+        self.crt_params = params;
+        self.preset = None;
+        self.queue.write_buffer(&self.crt_buffer, 0, bytemuck::cast_slice(&[params]));
+    }
+
+    /// The preset in use, `None` once custom parameters have been set.
+    pub fn preset(&self) -> Option<CrtPreset> {
+        self.preset
+    }
+
+    pub fn set_preset(&mut self, preset: CrtPreset) {
+        self.set_crt_params(preset.params());
+        self.preset = Some(preset);
+    }
+
+    /// Grid cell under a window position, following the letterboxing and the tube curvature.
+    /// Returns `None` for positions on the black bars, off the curved screen or in its
+    /// rounded corners.
+    pub fn cell_at(&self, position: PhysicalPosition<f64>) -> Option<(usize, usize)> {
+        let viewport = Viewport::letterbox(self.size, self.base_size, self.integer_scaling);
+        let u = (position.x as f32 - viewport.x) / viewport.width;
+        let v = (position.y as f32 - viewport.y) / viewport.height;
+        if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+            return None;
+        }
+
+        let [x, y] = self.crt_params.distort([u, v]);
+        if !(0.0..1.0).contains(&x) || !(0.0..1.0).contains(&y) || !self.crt_params.in_tube([x, y]) {
+            return None;
+        }
+        let columns = self.screen_buffer.width();
+        let rows = self.screen_buffer.height();
+        Some((((x * columns as f32) as usize).min(columns - 1), ((y * rows as f32) as usize).min(rows - 1)))
+    }
+
+    /// Restricts the grid to whole multiples of the configured `screen_size`.
+    pub fn set_integer_scaling(&mut self, integer_scaling: bool) {
+        self.integer_scaling = integer_scaling;
+        self.resize(self.size);
+    }
+
+    /// Draws and presents a frame.
+    ///
+    /// Lost or outdated surfaces are reconfigured and timeouts skip the frame; only
+    /// errors the renderer can't recover from are returned.
+    pub fn render(&mut self) -> Result<(), RenderError> {
+        self.prepare_frame();
+
+        #[cfg(test)]
+        if let Some(err) = self.injected_error.take() {
+            return self.recover(err);
+        }
+
+        match &self.target {
+            RenderTarget::Surface(surface, _) => {
+                let output = match surface.get_current_texture() {
+                    Ok(output) => output,
+                    Err(err) => return self.recover(err),
+                };
+                self.draw(&output.texture);
+                output.present();
+            }
+            RenderTarget::Offscreen(texture) => {
+                let texture = texture.clone();
+                self.draw(&texture);
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the next `render` behave as if acquiring the frame failed with `err`,
+    /// so recovery paths can be exercised without a real device loss.
+    #[cfg(test)]
+    pub(crate) fn inject_surface_error(&mut self, err: SurfaceError) {
+        self.injected_error = Some(err);
     }
-    pub fn init_instances(&mut self){
-        let instances = self.screen_buffer.iter().enumerate().map(|(i, &tile)|{
-            TileRenderData{
-                char : tile.char,
-                position: [i  % SCREEN_COLS, i  / SCREEN_COLS],
-                color : tile.color
-            }.get_instance_matrix()
-        }).collect::<Vec<_>>();
-
-
-        let instance_buffer = self.device.create_buffer_init(
-            &util::BufferInitDescriptor {
-                label: Some(format!("Instance Buffer").as_str()),
-                contents: bytemuck::cast_slice(&instances),
-                usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+
+    fn recover(&mut self, err: SurfaceError) -> Result<(), RenderError> {
+        match err {
+            SurfaceError::Lost | SurfaceError::Outdated => {
+                log::warn!("Reconfiguring surface: {err}");
+                self.resize(self.size);
+                Ok(())
+            }
+            SurfaceError::Timeout | SurfaceError::Other => {
+                log::warn!("Skipping frame: {err}");
+                Ok(())
             }
+            SurfaceError::OutOfMemory => Err(RenderError::OutOfMemory),
+        }
+    }
+
+    /// Renders a frame and reads the post-processed result back to the CPU.
+    ///
+    /// Headless renderers reuse their offscreen target; windowed renderers draw into
+    /// a temporary texture of the surface size so the swapchain is left untouched.
+    pub fn render_to_image(&mut self) -> anyhow::Result<RgbaImage> {
+        self.prepare_frame();
+
+        let texture = match &self.target {
+            RenderTarget::Offscreen(texture) => texture.clone(),
+            RenderTarget::Surface(..) => Self::create_output_texture(&self.device, self.format, self.size),
+        };
+        self.draw(&texture);
+        self.read_texture(&texture)
+    }
+
+    /// Renders the current frame again at `scale` times the surface size and reads it back.
+    ///
+    /// Every pass runs at the capture size, so larger scales add detail rather than
+    /// enlarging pixels. Afterglow and interlacing carry on as if no capture was taken.
+    pub fn screenshot(&mut self, scale: f32) -> anyhow::Result<RgbaImage> {
+        let width = (self.size.width as f32 * scale).round() as u32;
+        let height = (self.size.height as f32 * scale).round() as u32;
+        let max_dimension = self.device.limits().max_texture_dimension_2d;
+        ensure!(width > 0 && height > 0, "screenshot scale {scale} gives an empty image");
+        ensure!(width <= max_dimension && height <= max_dimension,
+            "screenshot of {width}x{height} exceeds the texture limit of {max_dimension}");
+
+        // Render the capture through its own targets at the capture size, starting
+        // from the live afterglow, then put the live frame state back so the
+        // capture doesn't count as a displayed frame.
+        let capture_size = PhysicalSize::new(width, height);
+        let viewport = Viewport::letterbox(capture_size, self.base_size, self.integer_scaling);
+        let capture_targets = FrameTargets::new(&self.device, self.format, &viewport, &self.texture_bind_group_layout, &self.sampler, &self.bloom_sampler, &Self::pass_scales(&self.post_stages));
+        self.copy_history(&capture_targets);
+        let live_targets = std::mem::replace(&mut self.targets, capture_targets);
+        let (frame_index, history_index) = (self.frame_index, self.history_index);
+
+        self.prepare_frame();
+        let texture = Self::create_output_texture(&self.device, self.format, capture_size);
+        self.draw(&texture);
+
+        self.targets = live_targets;
+        self.frame_index = frame_index;
+        self.history_index = history_index;
+        self.read_texture(&texture)
+    }
+
+    /// Scales each post pass's output of the last frame into the matching texture of `targets`.
+    fn copy_history(&self, targets: &FrameTargets) {
+        let previous = 1 - self.history_index;
+        let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("History Copy Encoder") });
+        for (views, bind_groups) in targets.pass_views.iter().zip(&self.targets.pass_bind_groups) {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("History Copy Pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &views[previous],
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color { r: 0.0, g: 0.0, b: 0.0, a: 1.0 }),
+                        store: StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(&self.blit_pipeline);
+            render_pass.set_bind_group(0, &bind_groups[previous], &[]);
+            render_pass.set_vertex_buffer(0, self.screen_mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(self.screen_mesh.index_buffer.slice(..), IndexFormat::Uint16);
+            render_pass.draw_indexed(0..self.screen_mesh.num_indices, 0, 0..1);
+        }
+        self.queue.submit(iter::once(encoder.finish()));
+    }
+
+    pub fn save_screenshot(&mut self, path: impl AsRef<Path>, scale: f32) -> anyhow::Result<()> {
+        self.screenshot(scale)?.save_with_format(path, ImageFormat::Png)?;
+        Ok(())
+    }
+
+    /// Rebuilds the pipelines that use the shader `file` from `source`.
+    ///
+    /// On a compile or validation error the current pipelines stay in place
+    /// and the error, including naga's diagnostic, is returned. New shared
+    /// declarations only apply to shaders reloaded after them.
+    pub async fn reload_shader(&mut self, file: &str, source: &str) -> anyhow::Result<()> {
+        match file {
+            SHARED_SHADER => self.shared_shader = source.to_string(),
+            TILE_SHADER => {
+                self.diffuse_render_pipeline = Self::create_pipeline_checked(&self.device, &self.shared_shader, file, source, |module| {
+                    Self::create_tile_pipeline(&self.device, module, &self.diffuse_render_pipeline_layout, self.format)
+                }).await?;
+            }
+            BLOOM_SHADER => {
+                (self.bloom_threshold_pipeline, self.bloom_blur_pipelines) = Self::create_pipeline_checked(&self.device, &self.shared_shader, file, source, |module| {
+                    Self::create_bloom_pipelines(&self.device, module, &self.bloom_pipeline_layout, self.format)
+                }).await?;
+            }
+            BLIT_SHADER => {
+                self.blit_pipeline = Self::create_pipeline_checked(&self.device, &self.shared_shader, file, source, |module| {
+                    Self::create_screen_pipeline(&self.device, "Blit Pipeline", module, "fs_main", &self.blit_pipeline_layout, self.format)
+                }).await?;
+            }
+            _ => {}
+        }
+        for stage in self.post_stages.iter_mut().filter(|stage| stage.shader == file) {
+            stage.pipeline = Self::create_pipeline_checked(&self.device, &self.shared_shader, file, source, |module| {
+                Self::create_screen_pipeline(&self.device, file, module, "fs_main", &self.post_render_pipeline_layout, self.format)
+            }).await?;
+        }
+        Ok(())
+    }
+
+    /// Native only: blocking on the error scope would stall the browser's event loop.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_changed_shaders(&mut self) {
+        for (file, source) in self.shader_watcher.changed_files() {
+            match pollster::block_on(self.reload_shader(&file, &source)) {
+                Ok(()) => log::info!("Reloaded {file}"),
+                Err(err) => log::error!("Keeping the previous pipeline: {err}"),
+            }
+            if file == SHARED_SHADER {
+                self.shader_watcher.mark_others_changed(SHARED_SHADER);
+            }
+        }
+    }
+
+    /// Compiles `source` followed by `shared` and builds pipelines from it with `build`,
+    /// turning wgpu validation errors into an `Err` instead of a panic.
+    async fn create_pipeline_checked<T>(device: &Device, shared: &str, file: &str, source: &str, build: impl FnOnce(&ShaderModule) -> T) -> anyhow::Result<T> {
+        device.push_error_scope(ErrorFilter::Validation);
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(file),
+            source: ShaderSource::Wgsl(format!("{source}\n{shared}").into()),
+        });
+        let pipelines = build(&module);
+        match device.pop_error_scope().await {
+            Some(err) => Err(anyhow!("{file}: {err}")),
+            None => Ok(pipelines),
+        }
+    }
+
+    /// Threshold pipeline and the horizontal and vertical blur pipelines of `bloom.wgsl`.
+    fn create_bloom_pipelines(device: &Device, module: &ShaderModule, layout: &PipelineLayout, format: TextureFormat) -> (RenderPipeline, [RenderPipeline; 2]) {
+        (
+            Self::create_screen_pipeline(device, "Bloom Threshold Pipeline", module, "fs_threshold", layout, format),
+            [
+                Self::create_screen_pipeline(device, "Bloom Horizontal Blur Pipeline", module, "fs_blur_horizontal", layout, format),
+                Self::create_screen_pipeline(device, "Bloom Vertical Blur Pipeline", module, "fs_blur_vertical", layout, format),
+            ],
+        )
+    }
+
+    fn create_tile_pipeline(device: &Device, module: &ShaderModule, layout: &PipelineLayout, format: TextureFormat) -> RenderPipeline {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Base Render Pipeline"),
+            layout: Some(layout),
+            vertex: VertexState {
+                module,
+                entry_point: Some("vs_main"),
+                buffers: &[Vertex::desc(), InstanceTileRaw::desc()],
+                compilation_options: PipelineCompilationOptions::default(),
+            },
+            fragment: Some(FragmentState {
+                module,
+                entry_point: Some("fs_main"),
+                targets: &[Some(ColorTargetState {
+                    format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+                compilation_options: PipelineCompilationOptions::default(),
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: true,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Output scales of the post passes, in chain order. The last pass always fills the viewport.
+    fn pass_scales(stages: &[PostStage]) -> Vec<f32> {
+        let last = stages.len() - 1;
+        stages.iter().enumerate().map(|(i, stage)| if i == last { 1.0 } else { stage.scale }).collect()
+    }
+
+    /// Fullscreen pass drawing `Vertex` quads from `screen_mesh` into a `format` target.
+    fn create_screen_pipeline(device: &Device, label: &str, module: &ShaderModule, fragment_entry: &str, layout: &PipelineLayout, format: TextureFormat) -> RenderPipeline {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
+            vertex: VertexState {
+                module,
+                entry_point: Some("vs_main"),
+                buffers: &[Vertex::desc()],
+                compilation_options: PipelineCompilationOptions::default(),
+            },
+            fragment: Some(FragmentState {
+                module,
+                entry_point: Some(fragment_entry),
+                targets: &[Some(ColorTargetState {
+                    format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+                compilation_options: PipelineCompilationOptions::default(),
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: true,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    fn create_output_texture(device: &Device, format: TextureFormat, size: PhysicalSize<u32>) -> Texture {
+        device.create_texture(&TextureDescriptor {
+            label: Some("Output texture"),
+            size : Extent3d{
+                width : size.width,
+                height : size.height,
+                depth_or_array_layers:1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        })
+    }
+
+    fn read_texture(&self, texture: &Texture) -> anyhow::Result<RgbaImage> {
+        let width = texture.width();
+        let height = texture.height();
+        let unpadded_bytes_per_row = 4 * width;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT) * COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let buffer = self.device.create_buffer(&BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: padded_bytes_per_row as BufferAddress * height as BufferAddress,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
         );
-        let num_instances = instances.len() as u32;
-        self.mesh.replace_instance(instance_buffer, num_instances);
+        self.queue.submit(iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(PollType::wait_indefinitely())?;
+        receiver.recv()??;
+
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+            }
+        }
+        buffer.unmap();
+
+        if matches!(texture.format(), TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb) {
+            for pixel in pixels.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        RgbaImage::from_raw(width, height, pixels).ok_or_else(|| anyhow!("readback size mismatch"))
     }
-    pub fn render(&self) -> Result<(), SurfaceError> {
+
+    fn prepare_frame(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.reload_changed_shaders();
+        self.update_instances();
 
         let render_time = Instant::now().duration_since(self.init_time);
-        let time_data:[f32;4] = [render_time.as_millis() as f32, 0.0, 0.0, 0.0];
+        let time_data:[f32;4] = [render_time.as_millis() as f32, self.frame_index as f32, 0.0, 0.0];
+        // Wrap while the index is still exact as an f32; 2^24 is even, so fields keep alternating.
+        self.frame_index = (self.frame_index + 1) % (1 << 24);
         self.queue.write_buffer(&self.time_buffer, 0, bytemuck::cast_slice(&[time_data]));
+    }
 
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&TextureViewDescriptor::default());
-
-
+    fn draw(&mut self, target: &Texture) {
+        let view = &target.create_view(&TextureViewDescriptor::default());
+        let viewport = Viewport::letterbox(PhysicalSize::new(target.width(), target.height()), self.base_size, self.integer_scaling);
         let mut encoder = self.device
             .create_command_encoder(&CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
@@ -481,7 +1281,7 @@ impl Renderer {
             let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
                 label: Some("Main Render Pass"),
                 color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &self.main_view,
+                    view: &self.targets.main_view,
                     resolve_target: None,
                     ops: Operations {
                         load: LoadOp::Clear(Color { r: 0.0, g: 0.0, b: 0.0, a: 1.0 }),
@@ -501,19 +1301,74 @@ impl Renderer {
                 Some(bg) => {
                     render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
                     render_pass.set_bind_group(1, bg.as_ref(), &[]);
+                    render_pass.set_bind_group(2, &self.time_bind_group, &[]);
                     render_pass.set_vertex_buffer(0, self.mesh.vertex_buffer.slice(..));
                     render_pass.set_vertex_buffer(1, self.mesh.instance_buffer.slice(..));
                     render_pass.set_index_buffer(self.mesh.index_buffer.slice(..), IndexFormat::Uint16);
-                    render_pass.draw_indexed(0..self.mesh.num_indices, 0, 0..self.mesh.num_instances);
+                    // Layers are drawn bottom to top from their own instance ranges,
+                    // blending each over the ones below.
+                    let layers = self.screen_buffer.layer_count() as u32;
+                    let cells = self.mesh.num_instances / layers;
+                    for layer in 0..layers {
+                        render_pass.draw_indexed(0..self.mesh.num_indices, 0, layer * cells..(layer + 1) * cells);
+                    }
                 }
             }
         }
 
-        {
+        // Bright parts of the tile pass are thresholded into the first bloom level
+        // and halved down the chain, each step averaging 2x2 texels through the
+        // linear bloom sampler. The last level is blurred horizontally into the
+        // blur texture and vertically back.
+        if self.crt_params.bloom_intensity > 0.0 {
+            let last = BLOOM_LEVELS - 1;
+            let mut passes = vec![("Bloom Threshold Pass", &self.bloom_threshold_pipeline, &self.targets.bloom_source_bind_group, &self.targets.bloom_views[0])];
+            for level in 1..BLOOM_LEVELS {
+                passes.push(("Bloom Downsample Pass", &self.blit_pipeline, &self.targets.bloom_bind_groups[level - 1], &self.targets.bloom_views[level]));
+            }
+            passes.push(("Bloom Horizontal Blur Pass", &self.bloom_blur_pipelines[0], &self.targets.bloom_bind_groups[last], &self.targets.bloom_blur_view));
+            passes.push(("Bloom Vertical Blur Pass", &self.bloom_blur_pipelines[1], &self.targets.bloom_blur_bind_group, &self.targets.bloom_views[last]));
+            for (label, pipeline, source, view) in passes {
+                let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                    label: Some(label),
+                    color_attachments: &[Some(RenderPassColorAttachment {
+                        view,
+                        resolve_target: None,
+                        ops: Operations {
+                            load: LoadOp::Clear(Color { r: 0.0, g: 0.0, b: 0.0, a: 1.0 }),
+                            store: StoreOp::Store,
+                        },
+                        depth_slice: None,
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                });
+                render_pass.set_pipeline(pipeline);
+
+                render_pass.set_bind_group(0, source, &[]);
+                render_pass.set_bind_group(1, &self.time_bind_group, &[]);
+                render_pass.set_vertex_buffer(0, self.screen_mesh.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(self.screen_mesh.index_buffer.slice(..), IndexFormat::Uint16);
+                render_pass.draw_indexed(0..self.screen_mesh.num_indices, 0, 0..1);
+            }
+        }
+
+        // Each post pass samples the previous one, starting from the tile pass, and
+        // its own output of the previous frame for effects such as afterglow. The
+        // last pass's output goes to the screen.
+        let current = self.history_index;
+        let previous = 1 - current;
+        for (i, stage) in self.post_stages.iter().enumerate() {
+            let view = &self.targets.pass_views[i][current];
+            let source = match i.checked_sub(1) {
+                Some(input) => &self.targets.pass_bind_groups[input][current],
+                None => &self.targets.post_process_bind_group,
+            };
             let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-                label: Some("Crt Post Process Pass"),
+                label: Some("Post Process Pass"),
                 color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &view,
+                    view,
                     resolve_target: None,
                     ops: Operations {
                         load: LoadOp::Clear(Color { r: 0.0, g: 0.0, b: 0.0, a: 1.0 }),
@@ -525,21 +1380,46 @@ impl Renderer {
                 timestamp_writes: None,
                 occlusion_query_set: None,
             });
-            render_pass.set_pipeline(&self.post_render_pipeline);
+            render_pass.set_pipeline(&stage.pipeline);
 
-            render_pass.set_bind_group(0, &self.post_process_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.time_bind_group , &[]);
+            render_pass.set_bind_group(0, source, &[]);
+            render_pass.set_bind_group(1, &stage.bind_group, &[]);
+            render_pass.set_bind_group(2, &self.targets.pass_bind_groups[i][previous], &[]);
+            render_pass.set_bind_group(3, &self.targets.bloom_bind_groups[BLOOM_LEVELS - 1], &[]);
             render_pass.set_vertex_buffer(0, self.screen_mesh.vertex_buffer.slice(..));
             render_pass.set_index_buffer(self.screen_mesh.index_buffer.slice(..), IndexFormat::Uint16);
             render_pass.draw_indexed(0..self.screen_mesh.num_indices, 0, 0..1);
+        }
 
+        {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Blit Pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color { r: 0.0, g: 0.0, b: 0.0, a: 1.0 }),
+                        store: StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(&self.blit_pipeline);
+            render_pass.set_viewport(viewport.x, viewport.y, viewport.width, viewport.height, 0.0, 1.0);
 
+            let output = self.targets.pass_bind_groups.last().expect("the post chain is never empty");
+            render_pass.set_bind_group(0, &output[current], &[]);
+            render_pass.set_vertex_buffer(0, self.screen_mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(self.screen_mesh.index_buffer.slice(..), IndexFormat::Uint16);
+            render_pass.draw_indexed(0..self.screen_mesh.num_indices, 0, 0..1);
         }
 
 
         self.queue.submit(iter::once(encoder.finish()));
-        output.present();
-        Ok(())
+        self.history_index = previous;
     }
 }
 
@@ -549,4 +1429,137 @@ const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     0.0, 1.0, 0.0, 0.0,
     0.0, 0.0, 0.5, 0.0,
     0.0, 0.0, 0.5, 1.0,
-);
\ No newline at end of file
+);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml::Spanned;
+
+    fn headless_renderer() -> Renderer {
+        headless_renderer_with(|_| {})
+    }
+
+    fn headless_renderer_with(configure: impl FnOnce(&mut GameConfig)) -> Renderer {
+        pollster::block_on(async {
+            let mut config = GameConfig::new().await.unwrap();
+            configure(&mut config);
+            Renderer::new_headless(&config, true).await.unwrap()
+        })
+    }
+
+    fn mean_brightness(image: &RgbaImage) -> f32 {
+        let total: u64 = image.pixels().map(|pixel| pixel[0] as u64 + pixel[1] as u64 + pixel[2] as u64).sum();
+        total as f32 / (image.width() * image.height() * 3) as f32
+    }
+
+    #[test]
+    fn recoverable_surface_errors_skip_the_frame() {
+        let mut renderer = headless_renderer();
+        let textures = |renderer: &Renderer| match &renderer.target {
+            RenderTarget::Offscreen(texture) => (texture.clone(), renderer.targets.main_view.texture().clone()),
+            RenderTarget::Surface(..) => unreachable!("headless renderers draw offscreen"),
+        };
+        for (err, reconfigures) in [(SurfaceError::Lost, true), (SurfaceError::Outdated, true), (SurfaceError::Timeout, false), (SurfaceError::Other, false)] {
+            let (output, main) = textures(&renderer);
+            renderer.inject_surface_error(err.clone());
+            assert_eq!(renderer.render(), Ok(()));
+            let (new_output, new_main) = textures(&renderer);
+            assert_eq!(new_output != output, reconfigures, "{err:?} output target");
+            assert_eq!(new_main != main, reconfigures, "{err:?} frame targets");
+        }
+        assert_eq!(renderer.render(), Ok(()));
+    }
+
+    #[test]
+    fn screenshots_render_at_scale_without_advancing_the_frame() {
+        let mut renderer = headless_renderer();
+        renderer.set_texture(include_bytes!("../res/chr.png")).unwrap();
+        renderer.render().unwrap();
+        let (frame_index, history_index) = (renderer.frame_index, renderer.history_index);
+
+        let image = renderer.screenshot(2.0).unwrap();
+        assert_eq!(image.dimensions(), (renderer.size.width * 2, renderer.size.height * 2));
+        assert!(image.pixels().any(|pixel| pixel[0] > 64 || pixel[1] > 64 || pixel[2] > 64));
+        assert_eq!((renderer.frame_index, renderer.history_index), (frame_index, history_index));
+        assert_eq!(renderer.targets.pass_views[0][0].texture().width(), renderer.size.width);
+    }
+
+    #[test]
+    fn passes_after_the_crt_pass_are_not_fed_back_into_the_afterglow() {
+        let mut renderer = headless_renderer_with(|config| {
+            config.post.push(Spanned::new(0..0, PostPass {
+                shader: "colour_grade.wgsl".to_string(),
+                scale: 1.0,
+                uniforms: vec![1.0, 1.0, 0.2, 1.0],
+            }));
+        });
+        renderer.set_texture(include_bytes!("../res/chr.png")).unwrap();
+        renderer.set_crt_params(CrtParams { persistence: [0.95; 3], noise_strength: 0.0, ..CrtParams::default() });
+
+        renderer.render().unwrap();
+        let first = mean_brightness(&renderer.screenshot(1.0).unwrap());
+        for _ in 0..10 {
+            renderer.render().unwrap();
+        }
+        let later = mean_brightness(&renderer.screenshot(1.0).unwrap());
+        assert!((later - first).abs() < 2.0, "brightness went from {first} to {later}");
+    }
+
+    #[test]
+    fn shader_errors_report_the_shaders_own_lines() {
+        let mut renderer = headless_renderer();
+        let source = format!("{}\nfn broken() -> f32 {{ return missing_name; }}\n", include_str!("../res/shader/blit.wgsl"));
+        let line = source.lines().count();
+
+        let err = pollster::block_on(renderer.reload_shader(BLIT_SHADER, &source)).unwrap_err().to_string();
+        assert!(err.contains(&format!("wgsl:{line}:")), "{err}");
+    }
+
+    #[test]
+    fn square_corners_leave_the_screen_lit() {
+        let mut renderer = headless_renderer();
+        renderer.set_texture(include_bytes!("../res/chr.png")).unwrap();
+        renderer.set_crt_params(CrtParams { corner_radius: 0.0, ..CrtParams::default() });
+
+        let brightness = mean_brightness(&renderer.screenshot(1.0).unwrap());
+        assert!(brightness > 5.0, "mean brightness {brightness}");
+    }
+
+    #[test]
+    fn bloom_brightens_the_frame() {
+        let mut renderer = headless_renderer();
+        renderer.set_texture(include_bytes!("../res/chr.png")).unwrap();
+        let (columns, rows) = (renderer.screen_buffer.width(), renderer.screen_buffer.height());
+        renderer.screen_buffer.fill_rect(0, 0, columns / 2, rows, b'#', [1.0; 3]);
+        let params = CrtParams { noise_strength: 0.0, bloom_threshold: 0.1, ..CrtParams::default() };
+
+        renderer.set_crt_params(params);
+        let plain = mean_brightness(&renderer.screenshot(1.0).unwrap());
+        renderer.set_crt_params(CrtParams { bloom_intensity: 1.0, ..params });
+        let bloomed = mean_brightness(&renderer.screenshot(1.0).unwrap());
+        assert!(bloomed > plain + 1.0, "bloom took the mean brightness from {plain} to {bloomed}");
+    }
+
+    #[test]
+    fn cell_at_skips_the_rounded_corners() {
+        let mut renderer = headless_renderer();
+        renderer.set_crt_params(CrtParams { corner_radius: 0.2, ..CrtParams::default() });
+        let viewport = Viewport::letterbox(renderer.size, renderer.base_size, renderer.integer_scaling);
+        let at = |u: f32, v: f32| PhysicalPosition::new((viewport.x + u * viewport.width) as f64, (viewport.y + v * viewport.height) as f64);
+
+        assert_eq!(renderer.cell_at(at(0.01, 0.01)), None);
+        assert_eq!(renderer.cell_at(at(0.99, 0.99)), None);
+        assert_eq!(renderer.cell_at(at(0.01, 0.5)), Some((0, renderer.screen_buffer.height() / 2)));
+        assert_eq!(renderer.cell_at(at(0.5, 0.01)), Some((renderer.screen_buffer.width() / 2, 0)));
+    }
+
+    #[test]
+    fn out_of_memory_is_reported() {
+        let mut renderer = headless_renderer();
+        renderer.inject_surface_error(SurfaceError::OutOfMemory);
+        assert_eq!(renderer.render(), Err(RenderError::OutOfMemory));
+        assert_eq!(renderer.render(), Ok(()));
+    }
+}